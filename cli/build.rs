@@ -17,7 +17,7 @@ You should have received a copy of the GNU Affero General Public License
 along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-use std::fs::{copy, create_dir};
+use std::fs::{copy, create_dir, create_dir_all};
 use std::path::Path;
 
 fn main() -> std::io::Result<()> {
@@ -59,6 +59,16 @@ fn main() -> std::io::Result<()> {
         tmp_path.join("core/src/lib.rs"),
     )?;
 
+    copy(
+        manifest_path.join("core/src/export.rs"),
+        tmp_path.join("core/src/export.rs"),
+    )?;
+
+    copy(
+        manifest_path.join("core/src/ext.rs"),
+        tmp_path.join("core/src/ext.rs"),
+    )?;
+
     let tmp_core_src_models_path = tmp_path.join("core/src/models");
 
     if !tmp_core_src_models_path.exists() {
@@ -124,6 +134,30 @@ fn main() -> std::io::Result<()> {
         tmp_path.join("engines/sqlite/src/lib.rs"),
     )?;
 
+    // MIGRATE
+
+    let tmp_migrate_path = tmp_path.join("migrate");
+
+    if !tmp_migrate_path.exists() {
+        create_dir(tmp_migrate_path)?;
+    }
+
+    copy(
+        manifest_path.join("migrate/Cargo.toml"),
+        tmp_path.join("migrate/Freight.toml"),
+    )?;
+
+    let tmp_migrate_src_path = tmp_path.join("migrate/src");
+
+    if !tmp_migrate_src_path.exists() {
+        create_dir(tmp_migrate_src_path)?;
+    }
+
+    copy(
+        manifest_path.join("migrate/src/main.rs"),
+        tmp_path.join("migrate/src/main.rs"),
+    )?;
+
     // PROTO
 
     let tmp_proto_path = tmp_path.join("proto");
@@ -182,6 +216,21 @@ fn main() -> std::io::Result<()> {
         tmp_path.join("server/src/main.rs"),
     )?;
 
+    copy(
+        manifest_path.join("server/src/auth.rs"),
+        tmp_path.join("server/src/auth.rs"),
+    )?;
+
+    copy(
+        manifest_path.join("server/src/config.rs"),
+        tmp_path.join("server/src/config.rs"),
+    )?;
+
+    copy(
+        manifest_path.join("server/src/ext.rs"),
+        tmp_path.join("server/src/ext.rs"),
+    )?;
+
     // SHIMS
 
     let tmp_shims_path = tmp_path.join("shims");
@@ -211,6 +260,11 @@ fn main() -> std::io::Result<()> {
         tmp_path.join("shims/src/lib.rs"),
     )?;
 
+    copy(
+        manifest_path.join("shims/src/ext.rs"),
+        tmp_path.join("shims/src/ext.rs"),
+    )?;
+
     let tmp_shims_src_models_path = tmp_path.join("shims/src/models");
 
     if !tmp_shims_src_models_path.exists() {
@@ -222,5 +276,33 @@ fn main() -> std::io::Result<()> {
         tmp_path.join("shims/src/models/example.rs"),
     )?;
 
+    // EXAMPLES, templates for `bicycle init`
+
+    let tmp_examples_path = tmp_path.join("examples");
+
+    if !tmp_examples_path.exists() {
+        create_dir(&tmp_examples_path)?;
+    }
+
+    for example in ["client", "offline", "sproc"] {
+        let tmp_example_src_path = tmp_examples_path.join(example).join("src");
+
+        if !tmp_example_src_path.exists() {
+            create_dir_all(tmp_example_src_path)?;
+        }
+
+        copy(
+            manifest_path.join("examples").join(example).join("Cargo.toml"),
+            tmp_examples_path.join(example).join("Freight.toml"),
+        )?;
+
+        for file in ["build.rs", "schema.proto", "README.md", "src/main.rs"] {
+            copy(
+                manifest_path.join("examples").join(example).join(file),
+                tmp_examples_path.join(example).join(file),
+            )?;
+        }
+    }
+
     Ok(())
 }
@@ -17,84 +17,309 @@ You should have received a copy of the GNU Affero General Public License
 along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-use std::env;
-use std::io::Write;
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::time::Instant;
-use std::{fs, fs::File};
 
 use prost::Message;
-use prost_types::FileDescriptorSet;
+use prost_types::{DescriptorProto, EnumDescriptorProto, FileDescriptorSet};
+
+use crate::evolution::{breaking_changes, previous_schema, PREVIOUS_SERVER, SCHEMA_DESCRIPTOR};
+use crate::migrations::collect_migrations;
+use crate::utils::{construct_model, is_model, is_well_known, schema_file, Schema, TypeScope};
+use crate::{gen, utils::Model, DEFAULT_PACKAGE, PRECOMPILE_DIR, SERVER_CONFIG};
+
+/// options for `build_with_options`.
+#[derive(Debug, Default, Clone)]
+pub struct BuildOptions {
+    /// build even when the schema has changed in ways that misread or lose
+    /// records stored by the previous build.
+    pub allow_breaking: bool,
+    /// package to declare the generated proto's types and services in, instead
+    /// of the schema's own package or `bicycle` when it has none.
+    pub package: Option<String>,
+    /// build the server with `cargo`'s dev profile instead of release, which
+    /// rebuilds faster.
+    pub debug: bool,
+    /// directory to generate and build the server in, instead of
+    /// `./__bicycle__`.
+    pub out_dir: Option<String>,
+    /// target triple to build the server for instead of the host's, i.e.
+    /// `x86_64-unknown-linux-musl`.
+    pub target: Option<String>,
+    /// features of the server crate to build with.
+    pub features: Vec<String>,
+}
+
+impl BuildOptions {
+    /// directory the server is generated and built in.
+    pub fn out_dir(&self) -> &str {
+        self.out_dir.as_deref().unwrap_or(PRECOMPILE_DIR)
+    }
+
+    /// path of the built server binary, relative to `out_dir`.
+    pub fn server_path(&self) -> String {
+        self.bin_path("bicycle_server")
+    }
+
+    /// path of a binary of the generated workspace built with these options,
+    /// relative to `out_dir`.
+    pub(crate) fn bin_path(&self, name: &str) -> String {
+        let mut path = PathBuf::from("./target");
+
+        if let Some(target) = self.target.as_deref() {
+            path.push(target);
+        }
+
+        path.push(if self.debug { "debug" } else { "release" });
+        path.push(name);
+
+        path.to_string_lossy().to_string()
+    }
+
+    /// `cargo build` arguments for a package of the generated workspace.
+    pub(crate) fn cargo_args<'a>(&'a self, package: &'a str) -> Vec<&'a str> {
+        let mut args = vec!["build", "-p", package];
+
+        if !self.debug {
+            args.push("--release");
+        }
+
+        if let Some(target) = self.target.as_deref() {
+            args.extend(["--target", target]);
+        }
 
-use crate::utils::construct_model;
-use crate::{gen, utils::Model, PRECOMPILE_DIR};
+        args
+    }
+
+    /// the `bicycle start` flags to run the server these options build.
+    fn start_args(&self) -> String {
+        let mut args = String::new();
+
+        if let Some(out_dir) = self.out_dir.as_deref() {
+            args = format!("{} --out-dir {}", args, out_dir);
+        }
+        if let Some(target) = self.target.as_deref() {
+            args = format!("{} --target {}", args, target);
+        }
+        if self.debug {
+            args = format!("{} --profile debug", args);
+        }
+
+        args
+    }
+}
+
+/// `--config` for the server when there's a `bicycle.toml` in the working
+/// directory, made absolute since the server runs in the output directory.
+pub fn server_config_args() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let path = Path::new(SERVER_CONFIG);
+
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    Ok(vec![
+        "--config".to_string(),
+        std::path::absolute(path)?.to_string_lossy().to_string(),
+    ])
+}
+
+fn is_valid_package(package: &str) -> bool {
+    package.split('.').all(|part| {
+        part.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
 
 /// builds BicycleDB components.
 ///
 /// * `schema_path` - path to the schema.proto file
 /// * `engine` - the database engine used (supports "sqlite" and "rocksdb")
 pub fn build(schema_path: &str, engine: &str) -> Result<(), Box<dyn std::error::Error>> {
-    if !Path::new(PRECOMPILE_DIR).exists() {
-        fs::create_dir(PRECOMPILE_DIR)?;
+    build_with_options(schema_path, engine, &BuildOptions::default())
+}
+
+/// builds BicycleDB components, see `BuildOptions`.
+///
+/// * `schema_path` - path to the schema.proto file
+/// * `engine` - the database engine used (supports "sqlite" and "rocksdb")
+/// * `options` - additional build options
+pub fn build_with_options(
+    schema_path: &str,
+    engine: &str,
+    options: &BuildOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let descriptor_bytes = generate(schema_path, engine, options)?;
+
+    let now = Instant::now();
+    println!("🛠️  building server...");
+
+    if let Err(err) = build_server(options) {
+        println!("failed to build server: {}", err);
+        exit(1)
     }
 
-    std::env::set_var("OUT_DIR", PRECOMPILE_DIR);
-    let precompile_dir = PathBuf::from(PRECOMPILE_DIR);
+    println!("🛠️  done building server. [{}ms]", now.elapsed().as_millis());
+
+    save_schema(descriptor_bytes, options)?;
 
-    let tmp_desc_path = precompile_dir.join("tmp_desc.bin");
+    println!("✅ done!");
+
+    println!(
+        "\n🚀 start server with `bicycle start{}`\n🚲 codegen with {}/proto/bicycle.proto",
+        options.start_args(),
+        options.out_dir()
+    );
 
-    tonic_build::configure()
+    Ok(())
+}
+
+/// checks the schema against the previous build and generates the
+/// `__bicycle__` workspace for it, returning the schema's descriptor set to
+/// save with `save_schema` once the server builds.
+pub(crate) fn generate(
+    schema_path: &str,
+    engine: &str,
+    options: &BuildOptions,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let out_dir = options.out_dir();
+
+    if !Path::new(out_dir).exists() {
+        fs::create_dir_all(out_dir)?;
+    }
+
+    // tonic names the code it writes after each proto package, which is only
+    // compiled to check the schema, so it all goes in a scratch directory
+    let precompile_dir = PathBuf::from(out_dir);
+    let scratch_dir = precompile_dir.join("tmp_proto");
+    let tmp_desc_path = scratch_dir.join("descriptor.bin");
+
+    fs::create_dir_all(&scratch_dir)?;
+
+    let compiled = tonic_build::configure()
+        .out_dir(&scratch_dir)
         .file_descriptor_set_path(&tmp_desc_path)
         .compile(&[&schema_path], &[schema_path.replace("schema.proto", "")])
-        .unwrap_or_else(|e| panic!("Failed to compile protos {:?}", e));
+        .and_then(|_| fs::read(&tmp_desc_path));
+
+    fs::remove_dir_all(&scratch_dir)?;
 
-    let descriptor_bytes = fs::read(&tmp_desc_path)?;
+    let descriptor_bytes = compiled.map_err(|e| format!("failed to compile protos: {}", e))?;
     let file_descriptor_set = FileDescriptorSet::decode(&descriptor_bytes[..])?;
 
+    if let Some(previous) = previous_schema(out_dir)? {
+        let changes = breaking_changes(&previous, &file_descriptor_set, options)?;
+
+        for change in changes.iter() {
+            eprintln!("⚠️  breaking change: {}", change);
+        }
+
+        if !changes.is_empty() && !options.allow_breaking {
+            return Err(
+                "schema has breaking changes, pass `--allow-breaking` to build anyway".into(),
+            );
+        }
+    }
+
+    let schema_name = schema_file(&file_descriptor_set).map(|file| file.name());
+    let scope = TypeScope::new(&file_descriptor_set);
+
+    let package = match options.package.as_deref() {
+        Some(package) => package,
+        None => schema_file(&file_descriptor_set)
+            .map(|file| file.package())
+            .filter(|package| !package.is_empty())
+            .unwrap_or(DEFAULT_PACKAGE),
+    };
+
+    if !is_valid_package(package) {
+        return Err(format!("'{}' isn't a valid package name", package).into());
+    }
+
     let mut models: Vec<Model> = vec![];
+    let mut helpers: Vec<DescriptorProto> = vec![];
+    let mut enums: Vec<EnumDescriptorProto> = vec![];
+    let mut imports: Vec<String> = vec![];
+
+    for file in file_descriptor_set.file.iter() {
+        if is_well_known(file.name()) {
+            continue;
+        }
 
-    for file in file_descriptor_set.file {
-        for message in file.message_type {
-            match construct_model(&message, true) {
-                Ok(model) => models.push(model),
-                Err(err) => eprintln!("{}", err),
+        for import in file.dependency.iter() {
+            if is_well_known(import) && !imports.contains(import) {
+                imports.push(import.to_string());
             }
         }
-    }
 
-    fs::remove_file(tmp_desc_path)?;
-    fs::remove_file(precompile_dir.join("bicycle.rs"))?;
+        // messages of imported files are declared alongside the models
+        let is_schema = Some(file.name()) == schema_name;
 
-    let now = Instant::now();
-    println!("📁 generating files...");
+        for (i, message) in file.message_type.iter().enumerate() {
+            if is_schema && is_model(file, i) {
+                let model = construct_model(message, true)
+                    .map_err(|err| format!("{}: {}", message.name(), err))?;
 
-    gen::gen(models, engine)?;
+                models.push(model);
+            } else {
+                helpers.push(message.clone());
+            }
+        }
+
+        enums.extend(file.enum_type.iter().cloned());
+    }
 
-    env::set_current_dir(PRECOMPILE_DIR)?;
+    let reserved = gen::reserved_names(&models);
+    let mut names: Vec<&str> = vec![];
+
+    for name in models
+        .iter()
+        .map(|model| model.name.as_str())
+        .chain(helpers.iter().map(|helper| helper.name()))
+        .chain(enums.iter().map(|enum_type| enum_type.name()))
+    {
+        if names.contains(&name) {
+            return Err(format!(
+                "'{}' is declared more than once across the schema and its imports",
+                name
+            )
+            .into());
+        }
 
-    if !Path::new("cli").exists() {
-        fs::create_dir("cli")?;
-        fs::create_dir("cli/src")?;
+        if reserved.iter().any(|reserved| reserved == name) {
+            return Err(format!(
+                "'{}' is also the name of a type bicycle generates, rename it",
+                name
+            )
+            .into());
+        }
 
-        let code = r#"
-fn main() {
-    println!("tmp");
-}
-        "#;
+        names.push(name);
+    }
 
-        let src_lib_path = Path::new("cli").join("src/lib.rs");
-        let src_main_path = Path::new("cli").join("src/main.rs");
-        let build_path = Path::new("cli").join("build.rs");
+    let schema = Schema {
+        models,
+        helpers,
+        enums,
+        imports,
+        package: package.to_string(),
+        scope,
+        migrations: collect_migrations(schema_path, out_dir)?,
+    };
 
-        let mut src_lib_file = File::create(src_lib_path)?;
-        src_lib_file.write_all(code.as_bytes())?;
+    let now = Instant::now();
+    println!("📁 generating files...");
 
-        let mut src_main_file = File::create(src_main_path)?;
-        src_main_file.write_all(code.as_bytes())?;
+    gen::gen(&schema, engine, out_dir)?;
 
-        let mut build_file = File::create(build_path)?;
-        build_file.write_all(code.as_bytes())?;
+    // builds before the generated workspace left out the CLI package needed a
+    // stub of it
+    let stub_dir = precompile_dir.join("cli");
+
+    if stub_dir.exists() {
+        fs::remove_dir_all(stub_dir)?;
     }
 
     println!(
@@ -102,26 +327,143 @@ fn main() {
         now.elapsed().as_millis()
     );
 
-    let now = Instant::now();
-    println!("🛠️  building server...");
+    Ok(descriptor_bytes)
+}
+
+/// builds the generated server, returning `cargo`'s errors if it fails.
+pub(crate) fn build_server(options: &BuildOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = options.cargo_args("bicycle_server");
+
+    let features = options.features.join(",");
+
+    if !features.is_empty() {
+        args.extend(["--features", &features]);
+    }
 
     let out = std::process::Command::new("cargo")
-        .args(["build", "--release", "-p", "bicycle_server"])
+        .args(args)
+        .current_dir(options.out_dir())
         .stderr(std::process::Stdio::piped())
         .output()?;
 
     if !out.status.success() {
-        println!("failed to build server: {}", String::from_utf8(out.stderr)?);
-        exit(1)
+        return Err(String::from_utf8(out.stderr)?.into());
     }
 
-    println!("🛠️  done building server. [{}ms]", now.elapsed().as_millis());
+    Ok(())
+}
 
-    println!("✅ done!");
-
-    println!(
-        "\n🚀 start server with `bicycle start`\n🚲 codegen with ./__bicycle__/proto/bicycle.proto"
-    );
+/// keeps the schema of a successful build, which the next one is checked
+/// against, along with the path of its server.
+pub(crate) fn save_schema(
+    descriptor_bytes: Vec<u8>,
+    options: &BuildOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let out_dir = Path::new(options.out_dir());
 
+    fs::write(out_dir.join(SCHEMA_DESCRIPTOR), descriptor_bytes)?;
+    fs::write(out_dir.join(PREVIOUS_SERVER), options.server_path())?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    use super::*;
+
+    /// generates a workspace for `schema` in a fresh temp directory, returning
+    /// the generated proto.
+    fn generate_schema(name: &str, schema: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let dir: PathBuf =
+            env::temp_dir().join(format!("bicycle_build_{}_{}", std::process::id(), name));
+
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+
+        fs::create_dir_all(&dir)?;
+
+        let schema_path = dir.join("schema.proto");
+        let out_dir = dir.join("__bicycle__");
+
+        fs::write(&schema_path, schema)?;
+
+        let options = BuildOptions {
+            out_dir: Some(out_dir.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+
+        let result = generate(&schema_path.to_string_lossy(), "sqlite", &options).and_then(|_| {
+            // the generated proto has to compile too
+            let status = Command::new(env::var("PROTOC").unwrap_or("protoc".to_string()))
+                .arg("-I")
+                .arg(out_dir.join("proto"))
+                .arg("-o")
+                .arg(dir.join("generated.bin"))
+                .arg("bicycle.proto")
+                .status()?;
+
+            if !status.success() {
+                return Err("protoc failed to compile the generated proto".into());
+            }
+
+            Ok(fs::read_to_string(out_dir.join("proto/bicycle.proto"))?)
+        });
+
+        fs::remove_dir_all(&dir)?;
+
+        result
+    }
+
+    #[test]
+    fn generates_schema_with_package() {
+        let proto = generate_schema(
+            "package",
+            "syntax = \"proto3\";\npackage shop;\nmessage Dog { string pk = 1; }\n",
+        )
+        .unwrap();
+
+        assert!(proto.contains("package shop;"));
+    }
+
+    #[test]
+    fn generates_schema_without_package() {
+        let proto = generate_schema(
+            "no_package",
+            "syntax = \"proto3\";\nmessage Dog { string pk = 1; }\n",
+        )
+        .unwrap();
+
+        assert!(proto.contains("package bicycle;"));
+    }
+
+    #[test]
+    fn rejects_names_bicycle_generates() {
+        for (name, schema) in [
+            ("built_in", "message Snapshot { string id = 1; }\n"),
+            ("model_change", "message DogChange { string id = 1; }\n"),
+        ] {
+            let schema = format!(
+                "syntax = \"proto3\";\nmessage Dog {{ string pk = 1; }}\n{}",
+                schema
+            );
+
+            assert!(generate_schema(name, &schema).is_err());
+        }
+    }
+
+    #[test]
+    fn generates_enum_values_named_like_change_ops() {
+        let proto = generate_schema(
+            "enum_values",
+            "syntax = \"proto3\";\nenum Action { PUT = 0; DELETE = 1; }\nmessage Dog { string pk = 1; Action action = 2; }\n",
+        )
+        .unwrap();
+
+        assert!(proto.contains("CHANGE_OP_PUT"));
+    }
+}
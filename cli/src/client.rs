@@ -0,0 +1,171 @@
+/*
+BicycleDB is a protobuf-defined database management system.
+
+Copyright (C) 2024 Ordinary Labs
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use tonic::body::BoxBody;
+use tonic::codegen::{http, Context, Poll, Service};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+
+use crate::DEFAULT_PACKAGE;
+
+/// a channel to a server built with another package than `bicycle`. the CLI's
+/// clients call `/bicycle.<Service>/<Method>`, which are sent on to
+/// `/<package>.<Service>/<Method>`. requests carry the token of
+/// `ConnectOptions` when there is one.
+#[derive(Debug, Clone)]
+pub struct PackageChannel {
+    channel: Channel,
+    package: String,
+    authorization: Option<http::HeaderValue>,
+}
+
+/// options for `PackageChannel::connect_with_options`.
+#[derive(Debug, Default, Clone)]
+pub struct ConnectOptions {
+    /// path to a PEM CA certificate to verify the server with, instead of the
+    /// system's trusted roots.
+    pub ca_cert: Option<String>,
+    /// path to a PEM client certificate, for servers that verify clients.
+    pub cert: Option<String>,
+    /// path to the PEM private key of `cert`.
+    pub key: Option<String>,
+    /// name to verify the server's certificate against, instead of the host
+    /// in its address.
+    pub domain: Option<String>,
+    /// API token or JWT sent as `authorization: Bearer <token>`, for servers
+    /// with auth enabled.
+    pub token: Option<String>,
+}
+
+impl ConnectOptions {
+    /// TLS config for an `https://` address.
+    fn tls(&self) -> Result<ClientTlsConfig, Box<dyn std::error::Error>> {
+        let mut tls = ClientTlsConfig::new();
+
+        if let Some(ca_cert) = self.ca_cert.as_deref() {
+            tls = tls.ca_certificate(Certificate::from_pem(read_pem(ca_cert)?));
+        }
+
+        match (self.cert.as_deref(), self.key.as_deref()) {
+            (Some(cert), Some(key)) => {
+                tls = tls.identity(Identity::from_pem(read_pem(cert)?, read_pem(key)?));
+            }
+            (None, None) => {}
+            _ => return Err("a client certificate and its key have to be passed together".into()),
+        }
+
+        if let Some(domain) = self.domain.as_deref() {
+            tls = tls.domain_name(domain);
+        }
+
+        Ok(tls)
+    }
+
+    fn is_tls(&self) -> bool {
+        self.ca_cert.is_some() || self.cert.is_some() || self.key.is_some() || self.domain.is_some()
+    }
+}
+
+fn read_pem(path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    std::fs::read(path).map_err(|err| format!("failed to read {}: {}", path, err).into())
+}
+
+impl PackageChannel {
+    /// * `addr` - address of the database (i.e http://0.0.0.0:50051)
+    /// * `package` - package the server was built with
+    pub async fn connect(
+        addr: String,
+        package: &str,
+    ) -> Result<PackageChannel, Box<dyn std::error::Error>> {
+        Self::connect_with_options(addr, package, &ConnectOptions::default()).await
+    }
+
+    /// connects with TLS when `addr` is `https://`, see `ConnectOptions`.
+    ///
+    /// * `addr` - address of the database (i.e https://db.example.com:50051)
+    /// * `package` - package the server was built with
+    /// * `options` - additional connection options
+    pub async fn connect_with_options(
+        addr: String,
+        package: &str,
+        options: &ConnectOptions,
+    ) -> Result<PackageChannel, Box<dyn std::error::Error>> {
+        let mut endpoint = Endpoint::from_shared(addr)?;
+
+        if endpoint.uri().scheme_str() == Some("https") {
+            endpoint = endpoint.tls_config(options.tls()?)?;
+        } else if options.is_tls() {
+            return Err("TLS options need an https:// address".into());
+        }
+
+        let authorization = match options.token.as_deref() {
+            Some(token) => {
+                let mut value: http::HeaderValue = format!("Bearer {}", token)
+                    .parse()
+                    .map_err(|_| "the token isn't a valid header value")?;
+
+                // kept out of debug output
+                value.set_sensitive(true);
+
+                Some(value)
+            }
+            None => None,
+        };
+
+        let channel = endpoint.connect().await?;
+
+        Ok(PackageChannel {
+            channel,
+            package: package.to_string(),
+            authorization,
+        })
+    }
+}
+
+impl Service<http::Request<BoxBody>> for PackageChannel {
+    type Response = <Channel as Service<http::Request<BoxBody>>>::Response;
+    type Error = <Channel as Service<http::Request<BoxBody>>>::Error;
+    type Future = <Channel as Service<http::Request<BoxBody>>>::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Service::<http::Request<BoxBody>>::poll_ready(&mut self.channel, cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<BoxBody>) -> Self::Future {
+        if let Some(authorization) = self.authorization.as_ref() {
+            req.headers_mut()
+                .insert(http::header::AUTHORIZATION, authorization.clone());
+        }
+
+        if self.package != DEFAULT_PACKAGE {
+            let prefix = format!("/{}.", DEFAULT_PACKAGE);
+
+            if let Some(path) = req.uri().path().strip_prefix(&prefix) {
+                let mut parts = req.uri().clone().into_parts();
+
+                parts.path_and_query = format!("/{}.{}", self.package, path).parse().ok();
+
+                if let Ok(uri) = http::Uri::from_parts(parts) {
+                    *req.uri_mut() = uri;
+                }
+            }
+        }
+
+        self.channel.call(req)
+    }
+}
@@ -0,0 +1,215 @@
+/*
+BicycleDB is a protobuf-defined database management system.
+
+Copyright (C) 2024 Ordinary Labs
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::build::{build_server, generate, save_schema, server_config_args, BuildOptions};
+use crate::migrations::MIGRATIONS_DIR;
+
+/// how often the schema's directory is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// `.proto` files, `.wasm` migrations and the sources of migration crates
+/// under `dir` with when they were last modified, leaving out the generated
+/// workspace in `out_dir`, `cargo` targets and hidden directories.
+fn watched_files(
+    dir: &Path,
+    out_dir: &Path,
+    in_migrations: bool,
+    files: &mut Vec<(PathBuf, SystemTime)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+
+        if path.is_dir() {
+            let is_out_dir = path.canonicalize().ok() == out_dir.canonicalize().ok();
+
+            if !name.starts_with('.') && name != "target" && !is_out_dir {
+                let in_migrations = in_migrations || name == MIGRATIONS_DIR;
+                watched_files(&path, out_dir, in_migrations, files)?;
+            }
+        } else if name.ends_with(".proto")
+            || name.ends_with(".wasm")
+            || (in_migrations && (name.ends_with(".rs") || name == "Cargo.toml"))
+        {
+            let modified = fs::metadata(&path)?.modified()?;
+            files.push((path, modified));
+        }
+    }
+
+    Ok(())
+}
+
+fn snapshot(
+    dir: &Path,
+    options: &BuildOptions,
+) -> Result<Vec<(PathBuf, SystemTime)>, Box<dyn std::error::Error>> {
+    let mut files = vec![];
+    watched_files(dir, Path::new(options.out_dir()), false, &mut files)?;
+    files.sort();
+
+    Ok(files)
+}
+
+/// regenerates and rebuilds the server, leaving the previous server binary in
+/// place when either fails.
+fn rebuild(
+    schema_path: &str,
+    engine: &str,
+    options: &BuildOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let descriptor_bytes = generate(schema_path, engine, options)?;
+
+    let now = Instant::now();
+    println!("🛠️  building server...");
+
+    build_server(options).map_err(|err| format!("failed to build server: {}", err))?;
+
+    println!(
+        "🛠️  done building server. [{}ms]",
+        now.elapsed().as_millis()
+    );
+
+    save_schema(descriptor_bytes, options)
+}
+
+fn start_server(
+    options: &BuildOptions,
+    log: &str,
+) -> Result<Option<Child>, Box<dyn std::error::Error>> {
+    let server_path = options.server_path();
+
+    if !Path::new(options.out_dir()).join(&server_path).exists() {
+        return Ok(None);
+    }
+
+    let child = Command::new(server_path)
+        .args(server_config_args()?)
+        .current_dir(options.out_dir())
+        .env("RUST_LOG", log)
+        .spawn()?;
+
+    Ok(Some(child))
+}
+
+fn stop_server(server: &mut Option<Child>) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(mut child) = server.take() {
+        if child.try_wait()?.is_none() {
+            child.kill()?;
+        }
+
+        child.wait()?;
+    }
+
+    Ok(())
+}
+
+/// waits for the watched files to change and then settle, since editors can
+/// save in several writes.
+fn wait_for_changes(
+    dir: &Path,
+    options: &BuildOptions,
+    files: &mut Vec<(PathBuf, SystemTime)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        sleep(POLL_INTERVAL);
+
+        let next = snapshot(dir, options)?;
+
+        if next != *files {
+            *files = next;
+            break;
+        }
+    }
+
+    loop {
+        sleep(POLL_INTERVAL);
+
+        let next = snapshot(dir, options)?;
+
+        if next == *files {
+            return Ok(());
+        }
+
+        *files = next;
+    }
+}
+
+/// builds and starts the server in the dev profile, then regenerates and
+/// rebuilds it whenever the schema, the `.proto` files next to it or its
+/// migrations change, restarting it on the same data once the build succeeds.
+/// runs until interrupted.
+///
+/// * `schema_path` - path to the schema.proto file
+/// * `engine` - the database engine used (supports "sqlite" and "rocksdb")
+/// * `options` - additional build options, always built with `debug`
+/// * `log` - log level for the database server
+pub fn dev(
+    schema_path: &str,
+    engine: &str,
+    options: &BuildOptions,
+    log: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let options = BuildOptions {
+        debug: true,
+        ..options.clone()
+    };
+
+    let dir = match Path::new(schema_path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut files = snapshot(dir, &options)?;
+    let mut server = None;
+
+    loop {
+        // the running server keeps serving until there's a new build to
+        // replace it with
+        match rebuild(schema_path, engine, &options) {
+            Ok(()) => {
+                stop_server(&mut server)?;
+                server = start_server(&options, log)?;
+            }
+            Err(err) => eprintln!("❌ {}", err),
+        }
+
+        // a failed first build can still leave one from an earlier session
+        if server.is_none() {
+            server = start_server(&options, log)?;
+        }
+
+        match server {
+            Some(_) => println!("🚀 server started, watching for changes..."),
+            None => println!("👀 no server built yet, watching for changes..."),
+        }
+
+        wait_for_changes(dir, &options, &mut files)?;
+
+        println!("\n🔁 schema changed, rebuilding...");
+    }
+}
@@ -0,0 +1,273 @@
+/*
+BicycleDB is a protobuf-defined database management system.
+
+Copyright (C) 2024 Ordinary Labs
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use prost_types::FileDescriptorSet;
+use prost_types::{field_descriptor_proto::Label, DescriptorProto, FieldDescriptorProto};
+
+use crate::build::server_config_args;
+use crate::utils::{
+    get_usable_type, is_map_entry, is_model, is_well_known, schema_file, TypeScope,
+};
+use crate::BuildOptions;
+
+/// descriptor set of the schema from the last successful build.
+pub(crate) const SCHEMA_DESCRIPTOR: &str = "schema_descriptor.bin";
+/// path of the server from the last successful build, relative to the output
+/// directory, since the next build can be for another profile or target.
+pub(crate) const PREVIOUS_SERVER: &str = "previous_server";
+
+/// a message along with the scope its types are resolved in.
+type Scoped<'a> = (&'a TypeScope, &'a DescriptorProto);
+
+fn field_type(field: &FieldDescriptorProto, (scope, message): Scoped) -> String {
+    let repeated = match field.label() {
+        Label::Repeated => "repeated ",
+        _ => "",
+    };
+
+    format!("{}{}", repeated, get_usable_type(field, message, scope))
+}
+
+fn compare_messages(path: &str, previous: Scoped, next: Scoped, changes: &mut Vec<String>) {
+    for old in previous.1.field.iter() {
+        match next.1.field.iter().find(|new| new.number() == old.number()) {
+            Some(new) if new.name() != old.name() => changes.push(format!(
+                "{}.{} reuses field number {} from {}.{}",
+                path,
+                new.name(),
+                old.number(),
+                path,
+                old.name()
+            )),
+            Some(new) if field_type(new, next) != field_type(old, previous) => {
+                changes.push(format!(
+                    "{}.{} changed type from '{}' to '{}'",
+                    path,
+                    old.name(),
+                    field_type(old, previous),
+                    field_type(new, next)
+                ))
+            }
+            _ => {}
+        }
+
+        match next.1.field.iter().find(|new| new.name() == old.name()) {
+            Some(new) if new.number() != old.number() => changes.push(format!(
+                "{}.{} was renumbered from {} to {}",
+                path,
+                old.name(),
+                old.number(),
+                new.number()
+            )),
+            None if old.name() == "pk" => changes.push(format!("{}.pk was removed", path)),
+            _ => {}
+        }
+    }
+
+    for old in previous.1.nested_type.iter() {
+        // map entries are compared as part of the map field's type
+        if is_map_entry(old) {
+            continue;
+        }
+
+        if let Some(new) = next
+            .1
+            .nested_type
+            .iter()
+            .find(|new| new.name() == old.name())
+        {
+            compare_messages(
+                &format!("{}.{}", path, old.name()),
+                (previous.0, old),
+                (next.0, new),
+                changes,
+            );
+        }
+    }
+}
+
+/// asks the server from the previous build whether `model` still has records.
+fn has_records(model: &str, options: &BuildOptions) -> Result<bool, Box<dyn std::error::Error>> {
+    let out_dir = Path::new(options.out_dir());
+    let previous_server = out_dir.join(PREVIOUS_SERVER);
+
+    // builds from before the server's path was kept
+    let server_path = if previous_server.exists() {
+        std::fs::read_to_string(previous_server)?.trim().to_string()
+    } else {
+        options.server_path()
+    };
+
+    if !out_dir.join(&server_path).exists() {
+        return Err(format!("no server built in {}", options.out_dir()).into());
+    }
+
+    let out = Command::new(server_path)
+        .args(server_config_args()?)
+        .args(["--has-records", model])
+        .current_dir(options.out_dir())
+        .stderr(Stdio::piped())
+        .output()?;
+
+    if !out.status.success() {
+        return Err(String::from_utf8(out.stderr)?.trim().into());
+    }
+
+    Ok(String::from_utf8(out.stdout)?.trim() == "true")
+}
+
+/// diffs the schema of the previous build against the next one, returning a
+/// description of each change that would misread or lose stored records.
+/// fails when a dropped model couldn't be checked for records, unless
+/// breaking changes are allowed.
+pub(crate) fn breaking_changes<'a>(
+    previous: &'a FileDescriptorSet,
+    next: &'a FileDescriptorSet,
+    options: &BuildOptions,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut changes = vec![];
+
+    let previous_scope = TypeScope::new(previous);
+    let next_scope = TypeScope::new(next);
+
+    let messages = |set: &'a FileDescriptorSet| {
+        set.file
+            .iter()
+            .filter(|file| !is_well_known(file.name()))
+            .flat_map(|file| {
+                file.message_type
+                    .iter()
+                    .enumerate()
+                    .map(move |(i, message)| (file, i, message))
+            })
+    };
+
+    let previous_schema = schema_file(previous).map(|file| file.name());
+
+    for (file, i, old) in messages(previous) {
+        let new = messages(next)
+            .map(|(_, _, message)| message)
+            .find(|new| new.name() == old.name());
+
+        match new {
+            Some(new) => compare_messages(
+                old.name(),
+                (&previous_scope, old),
+                (&next_scope, new),
+                &mut changes,
+            ),
+            // helper messages were never stored, so can always be dropped
+            None if Some(file.name()) == previous_schema && is_model(file, i) => {
+                match has_records(old.name(), options) {
+                    Ok(false) => {}
+                    Ok(true) => {
+                        changes.push(format!("{} was removed but still has records", old.name()))
+                    }
+                    Err(err) if options.allow_breaking => changes.push(format!(
+                        "{} was removed without checking for records: {}",
+                        old.name(),
+                        err
+                    )),
+                    // servers built before read-only checks can't open the
+                    // database while one runs, which isn't a breaking change
+                    Err(err) => {
+                        return Err(format!(
+                            "couldn't check whether the removed model {} still has records ({}); stop the server if it's running and retry, or pass `--allow-breaking` to remove it regardless",
+                            old.name(),
+                            err
+                        )
+                        .into())
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+
+    Ok(changes)
+}
+
+/// the schema descriptor set from the last successful build, if there was one.
+///
+/// * `out_dir` - directory the server is generated in
+pub(crate) fn previous_schema(
+    out_dir: &str,
+) -> Result<Option<FileDescriptorSet>, Box<dyn std::error::Error>> {
+    let path = Path::new(out_dir).join(SCHEMA_DESCRIPTOR);
+
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    Ok(Some(prost::Message::decode(&std::fs::read(path)?[..])?))
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_types::{field_descriptor_proto::Type, MessageOptions};
+
+    use super::*;
+
+    /// `Dog` with a nested `LogEntry` message whose `value` is of `value_type`.
+    fn dog(value_type: Type, map_entry: bool) -> DescriptorProto {
+        DescriptorProto {
+            name: Some("Dog".to_string()),
+            nested_type: vec![DescriptorProto {
+                name: Some("LogEntry".to_string()),
+                field: vec![FieldDescriptorProto {
+                    name: Some("value".to_string()),
+                    number: Some(1),
+                    r#type: Some(value_type as i32),
+                    ..Default::default()
+                }],
+                options: map_entry.then(|| MessageOptions {
+                    map_entry: Some(true),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn changes(previous: &DescriptorProto, next: &DescriptorProto) -> Vec<String> {
+        let scope = TypeScope::new(&FileDescriptorSet::default());
+        let mut changes = vec![];
+
+        compare_messages("Dog", (&scope, previous), (&scope, next), &mut changes);
+
+        changes
+    }
+
+    #[test]
+    fn compares_nested_messages_named_like_map_entries() {
+        assert_eq!(
+            changes(&dog(Type::String, false), &dog(Type::Int32, false)),
+            vec!["Dog.LogEntry.value changed type from 'string' to 'int32'".to_string()]
+        );
+    }
+
+    #[test]
+    fn skips_map_entries() {
+        assert!(changes(&dog(Type::String, true), &dog(Type::Int32, true)).is_empty());
+    }
+}
@@ -17,14 +17,18 @@ You should have received a copy of the GNU Affero General Public License
 along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+use std::collections::BTreeMap;
 use std::fs;
-use std::io::prelude::*;
 use std::path::Path;
 
-use heck::{ToShoutySnakeCase, ToSnakeCase};
+use heck::ToSnakeCase;
 use lazy_static::lazy_static;
 
-use crate::{utils::Model, PRECOMPILE_DIR};
+use crate::{
+    printer::{print_enum, print_message},
+    template::{block, splice, Template},
+    utils::{Model, Schema},
+};
 
 // BASE
 
@@ -45,6 +49,14 @@ const CORE_SRC_LIB_RS: &'static str = include_str!(concat!(
     env!("CARGO_MANIFEST_DIR"),
     "/cli/tmp/core/src/lib.rs"
 ));
+const CORE_SRC_EXPORT_RS: &'static str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/cli/tmp/core/src/export.rs"
+));
+const CORE_SRC_EXT_RS: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/cli/tmp/core/src/ext.rs"
+));
 
 // ENGINES
 
@@ -68,6 +80,17 @@ const ENGINES_SQLITE_SRC_LIB_RS: &'static str = include_str!(concat!(
     "/cli/tmp/engines/sqlite/src/lib.rs"
 ));
 
+// MIGRATE
+
+const MIGRATE_CARGO_TOML: &'static str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/cli/tmp/migrate/Freight.toml"
+));
+const MIGRATE_SRC_MAIN_RS: &'static str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/cli/tmp/migrate/src/main.rs"
+));
+
 // PROTO
 
 const PROTO_BUILD_RS: &'static str = include_str!(concat!(
@@ -97,6 +120,18 @@ const SERVER_SRC_MAIN_RS: &'static str = include_str!(concat!(
     env!("CARGO_MANIFEST_DIR"),
     "/cli/tmp/server/src/main.rs"
 ));
+const SERVER_SRC_AUTH_RS: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/cli/tmp/server/src/auth.rs"
+));
+const SERVER_SRC_CONFIG_RS: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/cli/tmp/server/src/config.rs"
+));
+const SERVER_SRC_EXT_RS: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/cli/tmp/server/src/ext.rs"
+));
 
 // SHIMS
 
@@ -112,77 +147,230 @@ const SHIMS_SRC_LIB_RS: &'static str = include_str!(concat!(
     env!("CARGO_MANIFEST_DIR"),
     "/cli/tmp/shims/src/lib.rs"
 ));
+const SHIMS_SRC_EXT_RS: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/cli/tmp/shims/src/ext.rs"
+));
 const SHIMS_SRC_MODELS_EXAMPLE_RS: &'static str = include_str!(concat!(
     env!("CARGO_MANIFEST_DIR"),
     "/cli/tmp/shims/src/models/example.rs"
 ));
 
-fn get_between(content: &str, from: &str, to: Option<&str>) -> String {
-    let start = match content.find(from) {
-        Some(i) => i + from.len() + 1,
-        None => 0,
-    };
+/// well-known type files the generated proto imports for its own messages.
+const PROTO_IMPORTS: [&str; 2] = [
+    "google/protobuf/empty.proto",
+    "google/protobuf/struct.proto",
+];
+/// version of the workspace crates in the generated `Cargo.toml`.
+const GENERATED_VERSION: &str = "0.0.0";
+/// replaces the `EXPIRES_AT` block of the core model for models with an
+/// `expires_at` field.
+const EXPIRES_AT_FN: &str = "fn expires_at(example: &bicycle_proto::Example) -> Option<u64> {\n    Some(example.expires_at).filter(|ts| *ts > 0)\n}\n";
 
-    if let Some(to) = to {
-        let end = match content.find(to) {
-            Some(i) => i - 4,
-            None => 0,
-        };
+lazy_static! {
+    static ref PROTO_MODEL_MESSAGES: Template =
+        Template::parse(block(PROTO_BICYCLE_PROTO, "MODEL_MESSAGES"));
+    static ref PROTO_MODEL_RPCS: Template =
+        Template::parse(block(PROTO_BICYCLE_PROTO, "MODEL_RPCS"));
+    static ref SERVER_HANDLERS: Template = Template::parse(block(SERVER_SRC_MAIN_RS, "HANDLERS"));
+    static ref SPROC_HOST_FNS: Template = Template::parse(block(CORE_SRC_LIB_RS, "HOST_FNS"));
+    static ref EXPORT_MODELS: Template =
+        Template::parse(block(CORE_SRC_EXPORT_RS, "EXPORT_MODELS"));
+    static ref MIGRATE_MODELS: Template =
+        Template::parse(block(MIGRATE_SRC_MAIN_RS, "MIGRATE_MODELS"));
+    static ref CORE_MODEL: Template = Template::parse(CORE_SRC_MODELS_EXAMPLE_RS);
+    static ref CORE_MODEL_EXPIRING: Template = Template::parse(&splice(
+        CORE_SRC_MODELS_EXAMPLE_RS,
+        "EXPIRES_AT",
+        EXPIRES_AT_FN
+    ));
+    static ref SHIMS_MODEL: Template = Template::parse(SHIMS_SRC_MODELS_EXAMPLE_RS);
+}
 
-        &content[start..end]
-    } else {
-        &content[start..]
-    }
-    .to_string()
+/// the types declared by `proto`, a proto file or block of one.
+fn declared_types(proto: &str) -> impl Iterator<Item = &str> {
+    proto.lines().filter_map(|line| {
+        let line = line.trim_start();
+
+        ["message ", "enum ", "service "]
+            .iter()
+            .find_map(|keyword| line.strip_prefix(keyword))
+            .and_then(|rest| {
+                rest.split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .next()
+            })
+    })
 }
 
-lazy_static! {
-    static ref WORKSPACE_ENGINE: String = get_between(
-        WORKSPACE_CARGO_TOML,
-        "##START_WORKSPACE_ENGINE##",
-        Some("##END_WORKSPACE_ENGINE##"),
-    );
-    static ref PROTO_MODEL_MESSAGES: String = get_between(
-        PROTO_BICYCLE_PROTO,
-        "##MODEL_MESSAGES_START##",
-        Some("##MODEL_MESSAGES_END##"),
-    );
-    static ref PROTO_MODEL_RPCS: String = get_between(
-        PROTO_BICYCLE_PROTO,
-        "##MODEL_RPCS_START##",
-        Some("##MODEL_RPCS_END##"),
-    );
-    static ref SERVER_HANDLERS: String = get_between(
-        SERVER_SRC_MAIN_RS,
-        "##START_HANDLERS##",
-        Some("##END_HANDLERS##")
-    );
-    static ref SPROC_HOST_FNS: String = get_between(
-        CORE_SRC_LIB_RS,
-        "##START_HOST_FNS##",
-        Some("##END_HOST_FNS##")
+/// names of the types the generated proto declares alongside the schema's
+/// messages and enums, in the same package, which those can't reuse. that's
+/// the built-in ones and those generated for each model, like `Dogs` and
+/// `DogChange` for `Dog`.
+pub(crate) fn reserved_names(models: &[Model]) -> Vec<String> {
+    let built_in = splice(
+        &splice(PROTO_BICYCLE_PROTO, "SCHEMA_TYPES", ""),
+        "MODEL_MESSAGES",
+        "",
     );
+    let mut names: Vec<String> = declared_types(&built_in).map(String::from).collect();
+
+    for model in models.iter() {
+        let messages = PROTO_MODEL_MESSAGES.render(model);
+        names.extend(declared_types(&messages).map(String::from));
+    }
+
+    names
 }
 
-fn create_dir(path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let path = format!("{}/{}", PRECOMPILE_DIR, path);
+/// records which files the last build generated and their content hashes.
+const MANIFEST: &str = "manifest.json";
+/// directories that only hold generated files, scanned for leftovers when a
+/// workspace was generated before builds kept a manifest.
+const GENERATED_DIRS: [&str; 2] = ["core/src/models", "shims/src/models"];
+
+/// FNV-1a, which unlike `DefaultHasher` hashes the same across builds of the
+/// CLI.
+fn content_hash(content: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
 
-    if Path::new(&path).exists() {
-        fs::remove_dir_all(path.clone())?;
+    for byte in content {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
     }
-    fs::create_dir(&path)?;
 
-    Ok(())
+    format!("{:016x}", hash)
+}
+
+/// the files of the `__bicycle__` workspace, by path relative to it.
+#[derive(Default)]
+struct Generated {
+    files: BTreeMap<String, String>,
+    /// written once and left as is by later builds, for custom code.
+    extensions: BTreeMap<String, String>,
 }
 
-fn write_file(path: &str, content: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let mut file = fs::File::create(format!("{}/{}", PRECOMPILE_DIR, path))?;
-    file.write_all(content.as_bytes())?;
+impl Generated {
+    fn write_file(&mut self, path: &str, content: &str) {
+        self.files.insert(path.to_string(), content.to_string());
+    }
+
+    fn write_extension(&mut self, path: &str, content: &str) {
+        self.extensions
+            .insert(path.to_string(), content.to_string());
+    }
+
+    /// writes the files whose content changed since the last build and removes
+    /// the ones it no longer generates. unchanged files keep their
+    /// modification times, so `cargo` only rebuilds what changed.
+    fn commit(self, out_dir: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let dir = Path::new(out_dir);
+        let manifest_path = dir.join(MANIFEST);
+
+        let previous: BTreeMap<String, String> = if manifest_path.exists() {
+            serde_json::from_slice(&fs::read(&manifest_path)?)?
+        } else {
+            scan_generated(dir)?
+        };
+
+        let mut manifest = BTreeMap::new();
 
-    Ok(())
+        for (path, content) in self.files.iter() {
+            let full_path = dir.join(path);
+            let hash = content_hash(content.as_bytes());
+
+            if full_path.exists() {
+                let current = content_hash(&fs::read(&full_path)?);
+
+                if current == hash {
+                    manifest.insert(path, hash);
+                    continue;
+                }
+
+                if previous
+                    .get(path)
+                    .is_some_and(|generated| *generated != current)
+                {
+                    eprintln!(
+                        "⚠️  overwriting changes to {}/{}, custom code belongs in the ext.rs files",
+                        out_dir, path
+                    );
+                }
+            }
+
+            if let Some(parent) = full_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            fs::write(&full_path, content)?;
+            manifest.insert(path, hash);
+        }
+
+        for path in previous.keys() {
+            let full_path = dir.join(path);
+
+            if !self.files.contains_key(path) && full_path.exists() {
+                fs::remove_file(full_path)?;
+            }
+        }
+
+        for (path, content) in self.extensions.iter() {
+            let full_path = dir.join(path);
+
+            if !full_path.exists() {
+                fs::write(full_path, content)?;
+            }
+        }
+
+        fs::write(manifest_path, serde_json::to_string_pretty(&manifest)?)?;
+
+        Ok(())
+    }
 }
 
-pub(crate) fn gen(models: Vec<Model>, engine: &str) -> Result<(), Box<dyn std::error::Error>> {
+/// the files in `GENERATED_DIRS` of `dir` and their content hashes, standing
+/// in for the manifest of a build that didn't write one.
+fn scan_generated(dir: &Path) -> Result<BTreeMap<String, String>, Box<dyn std::error::Error>> {
+    let mut files = BTreeMap::new();
+
+    for generated_dir in GENERATED_DIRS {
+        let full_dir = dir.join(generated_dir);
+
+        if !full_dir.exists() {
+            continue;
+        }
+
+        for entry in fs::read_dir(full_dir)? {
+            let full_path = entry?.path();
+
+            if !full_path.is_file() {
+                continue;
+            }
+
+            if let Some(name) = full_path.file_name().and_then(|name| name.to_str()) {
+                let path = format!("{}/{}", generated_dir, name);
+                files.insert(path, content_hash(&fs::read(&full_path)?));
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// generates the workspace the server is built in.
+///
+/// * `schema` - models and types of the schema
+/// * `engine` - the database engine used (supports "sqlite" and "rocksdb")
+/// * `out_dir` - directory to generate the workspace in
+pub(crate) fn gen(
+    schema: &Schema,
+    engine: &str,
+    out_dir: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let models = &schema.models;
+    let scope = &schema.scope;
+
+    let mut out = Generated::default();
+
     // BASE
 
     let mut sanitized_workspace_cargo_toml = WORKSPACE_CARGO_TOML.to_string();
@@ -192,92 +380,70 @@ pub(crate) fn gen(models: Vec<Model>, engine: &str) -> Result<(), Box<dyn std::e
         .unwrap();
 
     if let Some(version) = workspace_toml["workspace"]["package"]["version"].as_str() {
-        sanitized_workspace_cargo_toml = sanitized_workspace_cargo_toml
-            .replace(&format!("version = \"{}\"", version), "version = \"0.0.0\"");
+        sanitized_workspace_cargo_toml = sanitized_workspace_cargo_toml.replace(
+            &format!("version = \"{}\"", version),
+            &format!("version = \"{}\"", GENERATED_VERSION),
+        );
     }
 
-    let workspace_engine = WORKSPACE_ENGINE.replace("sqlite", engine);
+    let workspace_engine = format!(
+        "engine = {{ package = \"bicycle_{}\", path = \"./engines/{}\", version = \"{}\" }}\n",
+        engine, engine, GENERATED_VERSION
+    );
 
-    let sanitized_workspace_cargo_toml =
-        sanitized_workspace_cargo_toml.replace(&WORKSPACE_ENGINE.to_string(), &workspace_engine);
+    let sanitized_workspace_cargo_toml = splice(
+        &sanitized_workspace_cargo_toml,
+        "WORKSPACE_ENGINE",
+        &workspace_engine,
+    );
 
-    write_file("Cargo.toml", &sanitized_workspace_cargo_toml)?;
+    // the generated workspace doesn't include the CLI
+    let sanitized_workspace_cargo_toml = splice(&sanitized_workspace_cargo_toml, "CLI_PACKAGE", "");
 
-    // CORE
-    create_dir("core")?;
-    write_file("core/Cargo.toml", CORE_CARGO_TOML)?;
+    out.write_file("Cargo.toml", &sanitized_workspace_cargo_toml);
 
-    create_dir("core/src")?;
-    create_dir("core/src/models")?;
+    // CORE
+    out.write_file("core/Cargo.toml", CORE_CARGO_TOML);
 
     // ENGINES
-    create_dir("engines")?;
 
     // RocksDB
-    create_dir("engines/rocksdb")?;
-    write_file("engines/rocksdb/Cargo.toml", ENGINES_ROCKSDB_CARGO_TOML)?;
-
-    create_dir("engines/rocksdb/src")?;
-    write_file("engines/rocksdb/src/lib.rs", ENGINES_ROCKSDB_SRC_LIB_RS)?;
+    out.write_file("engines/rocksdb/Cargo.toml", ENGINES_ROCKSDB_CARGO_TOML);
+    out.write_file("engines/rocksdb/src/lib.rs", ENGINES_ROCKSDB_SRC_LIB_RS);
 
     // SQLite
-    create_dir("engines/sqlite")?;
-    write_file("engines/sqlite/Cargo.toml", ENGINES_SQLITE_CARGO_TOML)?;
+    out.write_file("engines/sqlite/Cargo.toml", ENGINES_SQLITE_CARGO_TOML);
+    out.write_file("engines/sqlite/src/lib.rs", ENGINES_SQLITE_SRC_LIB_RS);
 
-    create_dir("engines/sqlite/src")?;
-    write_file("engines/sqlite/src/lib.rs", ENGINES_SQLITE_SRC_LIB_RS)?;
+    // MIGRATE
+    out.write_file("migrate/Cargo.toml", MIGRATE_CARGO_TOML);
 
     // PROTO
 
-    create_dir("proto")?;
-    write_file("proto/build.rs", PROTO_BUILD_RS)?;
-    write_file("proto/Cargo.toml", PROTO_CARGO_TOML)?;
-
-    create_dir("proto/src")?;
-    write_file("proto/src/lib.rs", PROTO_SRC_LIB_RS)?;
+    out.write_file("proto/build.rs", PROTO_BUILD_RS);
+    out.write_file("proto/Cargo.toml", PROTO_CARGO_TOML);
+    out.write_file(
+        "proto/src/lib.rs",
+        &include_package(PROTO_SRC_LIB_RS, &schema.package),
+    );
 
     // SERVER
-    create_dir("server")?;
-    create_dir("server/src")?;
-    write_file("server/Cargo.toml", &SERVER_CARGO_TOML)?;
+    out.write_file("server/Cargo.toml", SERVER_CARGO_TOML);
 
     // SHIMS
 
-    create_dir("shims")?;
-    write_file("shims/build.rs", SHIMS_BUILD_RS)?;
-    write_file("shims/Cargo.toml", SHIMS_CARGO_TOML)?;
-
-    create_dir("shims/src")?;
-    write_file("shims/src/lib.rs", SHIMS_SRC_LIB_RS)?;
-
-    create_dir("shims/src/models")?;
+    out.write_file("shims/build.rs", SHIMS_BUILD_RS);
+    out.write_file("shims/Cargo.toml", SHIMS_CARGO_TOML);
 
-    let mut rpc_block = "".to_string();
-    let mut messages_block = "".to_string();
+    out.write_file(
+        "shims/src/lib.rs",
+        &include_package(SHIMS_SRC_LIB_RS, &schema.package),
+    );
 
     let mut core_models_mod_rs = "".to_string();
     let mut shims_models_mod_rs = "".to_string();
 
-    let mut server_handlers_block = "".to_string();
-
-    let mut sprocs_host_fns_block = "".to_string();
-
-    for (i, model) in models.iter().enumerate() {
-        let (rpc_chunk, messages_chunk) = gen_proto(&model);
-
-        rpc_block = format!(
-            "{}{}{}",
-            rpc_block,
-            if i == 0 { "" } else { "\n" },
-            rpc_chunk
-        );
-        messages_block = format!(
-            "{}{}{}",
-            messages_block,
-            if i == 0 { "" } else { "\n" },
-            messages_chunk
-        );
-
+    for model in models.iter() {
         core_models_mod_rs = format!(
             "{}mod {};\npub use {}::*;",
             core_models_mod_rs,
@@ -285,19 +451,15 @@ pub(crate) fn gen(models: Vec<Model>, engine: &str) -> Result<(), Box<dyn std::e
             model.name.to_snake_case()
         );
 
-        let core_src_model_rs_content =
-            replace_model_name(&model, &CORE_SRC_MODELS_EXAMPLE_RS.to_string());
+        let core_model: &Template = if model.has_expires_at {
+            &CORE_MODEL_EXPIRING
+        } else {
+            &CORE_MODEL
+        };
 
-        write_file(
+        out.write_file(
             &format!("core/src/models/{}.rs", model.name.to_snake_case()),
-            &core_src_model_rs_content,
-        )?;
-
-        server_handlers_block = format!(
-            "{}{}{}",
-            server_handlers_block,
-            if i == 0 { "" } else { "\n" },
-            replace_model_name(&model, &SERVER_HANDLERS)
+            &core_model.render(model),
         );
 
         shims_models_mod_rs = format!(
@@ -307,114 +469,137 @@ pub(crate) fn gen(models: Vec<Model>, engine: &str) -> Result<(), Box<dyn std::e
             model.name.to_snake_case()
         );
 
-        let shims_src_model_rs_content =
-            replace_model_name(&model, &SHIMS_SRC_MODELS_EXAMPLE_RS.to_string());
-
-        write_file(
+        out.write_file(
             &format!("shims/src/models/{}.rs", model.name.to_snake_case()),
-            &shims_src_model_rs_content,
-        )?;
-
-        sprocs_host_fns_block = format!(
-            "{}{}{}",
-            sprocs_host_fns_block,
-            if i == 0 { "" } else { "\n" },
-            replace_model_name(&model, &SPROC_HOST_FNS)
+            &SHIMS_MODEL.render(model),
         );
     }
 
     // CORE
-    let core_src_lib_rs =
-        CORE_SRC_LIB_RS.replace(&SPROC_HOST_FNS.to_string(), &sprocs_host_fns_block);
-    write_file("core/src/lib.rs", &core_src_lib_rs)?;
-    write_file("core/src/models/mod.rs", &core_models_mod_rs)?;
-
-    // PROTO
-    let proto = PROTO_BICYCLE_PROTO
-        .replace(&PROTO_MODEL_RPCS.to_string(), &rpc_block)
-        .replace(&PROTO_MODEL_MESSAGES.to_string(), &messages_block);
-
-    write_file("proto/bicycle.proto", &proto)?;
+    let migrations = schema
+        .migrations
+        .iter()
+        .map(|version| {
+            format!(
+                "    ({}, include_bytes!(\"../../migrations/{}.wasm\")),\n",
+                version, version
+            )
+        })
+        .collect::<String>();
+
+    let core_src_lib_rs = splice(
+        &splice(
+            CORE_SRC_LIB_RS,
+            "HOST_FNS",
+            &SPROC_HOST_FNS.render_all(models, "\n"),
+        ),
+        "MIGRATIONS",
+        &migrations,
+    );
+    out.write_file("core/src/lib.rs", &core_src_lib_rs);
 
-    // SERVER
-    let server_src_main_rs =
-        SERVER_SRC_MAIN_RS.replace(&SERVER_HANDLERS.to_string(), &server_handlers_block);
-    write_file("server/src/main.rs", &server_src_main_rs)?;
+    let core_src_export_rs = splice(
+        CORE_SRC_EXPORT_RS,
+        "EXPORT_MODELS",
+        &EXPORT_MODELS.render_all(models, ""),
+    );
+    out.write_file("core/src/export.rs", &core_src_export_rs);
+    out.write_file("core/src/models/mod.rs", &core_models_mod_rs);
+
+    // MIGRATE
+    let migrate_src_main_rs = splice(
+        MIGRATE_SRC_MAIN_RS,
+        "MIGRATE_MODELS",
+        &MIGRATE_MODELS.render_all(models, ""),
+    );
+    out.write_file("migrate/src/main.rs", &migrate_src_main_rs);
 
-    // SHIMS
-    write_file("shims/src/models/mod.rs", &shims_models_mod_rs)?;
+    // PROTO
 
-    Ok(())
-}
+    // the models are printed from the schema, followed by the helper messages
+    // and top-level enums they share
+    let schema_types = models
+        .iter()
+        .map(|model| print_message(&model.message, scope, 0))
+        .chain(
+            schema
+                .helpers
+                .iter()
+                .map(|helper| print_message(helper, scope, 0)),
+        )
+        .chain(
+            schema
+                .enums
+                .iter()
+                .map(|enum_type| print_enum(enum_type, 0)),
+        )
+        .map(|printed| format!("{}\n", printed))
+        .collect::<String>();
+
+    let imports = PROTO_IMPORTS
+        .iter()
+        .copied()
+        .chain(
+            schema
+                .imports
+                .iter()
+                .map(|import| import.as_str())
+                .filter(|import| !PROTO_IMPORTS.contains(import)),
+        )
+        .map(|import| format!("import \"{}\";\n", import))
+        .collect::<String>();
 
-fn gen_proto(model: &Model) -> (String, String) {
-    let rpc_chunk = PROTO_MODEL_RPCS.replace("Example", &model.name);
-
-    let mut messages_chunk = replace_model_name(&model, &PROTO_MODEL_MESSAGES);
-
-    let nested_messages_block = get_nested_messages_block(&model);
-    let properties_block = get_properties_block(&model);
-
-    messages_chunk = messages_chunk.replace(
-        "  string pk = 1;",
-        &format!(
-            "{}{}{}",
-            properties_block,
-            if nested_messages_block != "" {
-                "\n\n"
-            } else {
-                ""
-            },
-            nested_messages_block,
-        ),
+    let proto = splice(
+        PROTO_BICYCLE_PROTO,
+        "PACKAGE",
+        &format!("package {};\n", schema.package),
+    );
+    let proto = splice(&proto, "IMPORTS", &imports);
+    let proto = splice(&proto, "SCHEMA_TYPES", &schema_types);
+    let proto = splice(
+        &proto,
+        "MODEL_MESSAGES",
+        &PROTO_MODEL_MESSAGES.render_all(models, ""),
+    );
+    let proto = splice(
+        &proto,
+        "MODEL_RPCS",
+        &PROTO_MODEL_RPCS.render_all(models, ""),
     );
 
-    (rpc_chunk, messages_chunk)
-}
+    out.write_file("proto/bicycle.proto", &proto);
 
-fn get_properties_block(model: &Model) -> String {
-    let mut properties_block = "".to_string();
-
-    for (i, property) in model.properties.iter().enumerate() {
-        properties_block = format!(
-            "{}{}  {} {} = {};",
-            properties_block,
-            if i == 0 { "" } else { "\n" },
-            property._type,
-            property.name,
-            property.number
-        )
-    }
+    // SERVER
+    let server_src_main_rs = splice(
+        SERVER_SRC_MAIN_RS,
+        "HANDLERS",
+        &SERVER_HANDLERS.render_all(models, "\n"),
+    );
+    out.write_file("server/src/main.rs", &server_src_main_rs);
+    out.write_file("server/src/auth.rs", SERVER_SRC_AUTH_RS);
+    out.write_file("server/src/config.rs", SERVER_SRC_CONFIG_RS);
 
-    properties_block
-}
+    // SHIMS
+    out.write_file("shims/src/models/mod.rs", &shims_models_mod_rs);
 
-fn get_nested_messages_block(model: &Model) -> String {
-    let mut nested_messages_block = "".to_string();
-
-    for model in model.nested_models.iter() {
-        let nested_messages_chunk = get_nested_messages_block(&model);
-        let properties_block = get_properties_block(&model);
-
-        nested_messages_block = format!(
-            "\nmessage {} {{\n{}{}{}\n}}",
-            model.name,
-            properties_block,
-            if nested_messages_block != "" {
-                "\n\n"
-            } else {
-                ""
-            },
-            nested_messages_chunk,
-        );
-    }
+    // EXTENSIONS
+    out.write_extension("core/src/ext.rs", CORE_SRC_EXT_RS);
+    out.write_extension("server/src/ext.rs", SERVER_SRC_EXT_RS);
+    out.write_extension("shims/src/ext.rs", SHIMS_SRC_EXT_RS);
 
-    nested_messages_block
+    out.commit(out_dir)
 }
 
-fn replace_model_name(model: &Model, template: &String) -> String {
-    template
-        .replace("example", &model.name.to_snake_case())
-        .replace("Example", &model.name)
-        .replace("EXAMPLE", &model.name.to_shouty_snake_case())
+/// `source` including the generated code of `package` rather than `bicycle`.
+fn include_package(source: &str, package: &str) -> String {
+    let indent: String = block(source, "INCLUDE_PROTO")
+        .chars()
+        .take_while(|c| *c == ' ')
+        .collect();
+
+    splice(
+        source,
+        "INCLUDE_PROTO",
+        &format!("{}tonic::include_proto!(\"{}\");\n", indent, package),
+    )
 }
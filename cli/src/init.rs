@@ -0,0 +1,259 @@
+/*
+BicycleDB is a protobuf-defined database management system.
+
+Copyright (C) 2024 Ordinary Labs
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::fs;
+use std::path::Path;
+
+/// a project from `examples`, used as a template.
+struct Example {
+    cargo_toml: &'static str,
+    build_rs: &'static str,
+    main_rs: &'static str,
+    schema_proto: &'static str,
+    readme: &'static str,
+}
+
+const CLIENT: Example = Example {
+    cargo_toml: include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/cli/tmp/examples/client/Freight.toml"
+    )),
+    build_rs: include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/cli/tmp/examples/client/build.rs"
+    )),
+    main_rs: include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/cli/tmp/examples/client/src/main.rs"
+    )),
+    schema_proto: include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/cli/tmp/examples/client/schema.proto"
+    )),
+    readme: include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/cli/tmp/examples/client/README.md"
+    )),
+};
+
+const OFFLINE: Example = Example {
+    cargo_toml: include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/cli/tmp/examples/offline/Freight.toml"
+    )),
+    build_rs: include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/cli/tmp/examples/offline/build.rs"
+    )),
+    main_rs: include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/cli/tmp/examples/offline/src/main.rs"
+    )),
+    schema_proto: include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/cli/tmp/examples/offline/schema.proto"
+    )),
+    readme: include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/cli/tmp/examples/offline/README.md"
+    )),
+};
+
+const SPROC: Example = Example {
+    cargo_toml: include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/cli/tmp/examples/sproc/Freight.toml"
+    )),
+    build_rs: include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/cli/tmp/examples/sproc/build.rs"
+    )),
+    main_rs: include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/cli/tmp/examples/sproc/src/main.rs"
+    )),
+    schema_proto: include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/cli/tmp/examples/sproc/schema.proto"
+    )),
+    readme: include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/cli/tmp/examples/sproc/README.md"
+    )),
+};
+
+const SERVER_README: &str = "# NAME
+
+## Build The Database Server
+
+```bash
+## generate the __bicycle__ build, re-run after changing schema.proto
+bicycle build schema.proto --engine ENGINE
+```
+
+## Run The Database Server
+
+```bash
+bicycle start
+```
+";
+
+const GITIGNORE: &str = "/target\n/__bicycle__\n";
+
+/// `source` without the license comment the examples start with.
+fn strip_license(source: &str) -> &str {
+    let source = match source.strip_prefix("/*") {
+        Some(rest) => rest
+            .split_once("*/")
+            .map(|(_, rest)| rest)
+            .unwrap_or(source),
+        None => source,
+    };
+
+    source.trim_start()
+}
+
+/// the example's manifest, made into a standalone project depending on the
+/// published `bicycle` build functions.
+fn cargo_toml(example: &Example, name: &str) -> String {
+    let mut lines = vec![];
+
+    for line in example
+        .cargo_toml
+        .lines()
+        .skip_while(|line| line.starts_with('#') || line.trim().is_empty())
+    {
+        if line.starts_with("authors = ") || line.starts_with("license = ") {
+            continue;
+        }
+
+        if line.starts_with("name = \"example_") {
+            lines.push(format!("name = \"{}\"", name));
+        } else if line == "bicycle = { path = \"../..\" }" {
+            lines.push(format!("bicycle = \"{}\"", env!("CARGO_PKG_VERSION")));
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+
+    format!("{}\n", lines.join("\n"))
+}
+
+/// the example's README, with the CLI run as `bicycle` rather than from this
+/// repository.
+fn readme(example: &Example, name: &str, engine: &str) -> String {
+    let mut lines = vec![format!("# {}", name)];
+
+    for line in example.readme.lines().skip(1) {
+        if line.starts_with("(swap the `cargo run") {
+            continue;
+        }
+
+        lines.push(
+            line.replace("cargo run --manifest-path ../../Cargo.toml --", "bicycle")
+                .replace("--engine sqlite", &format!("--engine {}", engine)),
+        );
+    }
+
+    // the dropped note was followed by a blank line
+    lines.dedup_by(|a, b| a.is_empty() && b.is_empty());
+
+    format!("{}\n", lines.join("\n"))
+}
+
+fn write_new(dir: &Path, path: &str, content: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let path = dir.join(path);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(path, content)?;
+
+    Ok(())
+}
+
+/// creates a new project with a `schema.proto` to start from.
+///
+/// * `dir` - new or empty directory for the project, also its name
+/// * `template` - "server" for just the schema, "client" for a crate calling
+///   a running server, "offline" for a crate embedding the database or
+///   "sproc" for a Biplane function crate
+/// * `engine` - the database engine the project builds with ("sqlite" or
+///   "rocksdb")
+pub fn init(dir: &str, template: &str, engine: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = Path::new(dir);
+
+    if dir.exists() && fs::read_dir(dir)?.next().is_some() {
+        return Err(format!("'{}' is not empty", dir.display()).into());
+    }
+
+    fs::create_dir_all(dir)?;
+
+    let name: String = dir
+        .canonicalize()?
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or("project directory has no name")?
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let example = match template {
+        "server" => {
+            write_new(dir, "schema.proto", strip_license(CLIENT.schema_proto))?;
+            write_new(
+                dir,
+                "README.md",
+                &SERVER_README
+                    .replace("NAME", &name)
+                    .replace("ENGINE", engine),
+            )?;
+            write_new(dir, ".gitignore", GITIGNORE)?;
+
+            return Ok(());
+        }
+        "client" => CLIENT,
+        "offline" => OFFLINE,
+        "sproc" => SPROC,
+        _ => return Err(format!("no '{}' template", template).into()),
+    };
+
+    write_new(dir, "Cargo.toml", &cargo_toml(&example, &name))?;
+    write_new(
+        dir,
+        "build.rs",
+        &strip_license(example.build_rs).replace(
+            "bicycle::build(schema_path, \"sqlite\")",
+            &format!("bicycle::build(schema_path, \"{}\")", engine),
+        ),
+    )?;
+    write_new(dir, "src/main.rs", strip_license(example.main_rs))?;
+    write_new(dir, "schema.proto", strip_license(example.schema_proto))?;
+    write_new(dir, "README.md", &readme(&example, &name, engine))?;
+    write_new(dir, ".gitignore", GITIGNORE)?;
+
+    Ok(())
+}
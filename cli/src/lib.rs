@@ -21,8 +21,36 @@ along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 pub(crate) const PRECOMPILE_DIR: &'static str = "./__bicycle__";
 
+/// package the generated proto declares its types and services in, unless the
+/// schema has its own or the build sets another.
+pub const DEFAULT_PACKAGE: &str = "bicycle";
+
+/// config file the server reads its runtime settings from, passed along when
+/// it's in the directory the CLI runs in.
+pub const SERVER_CONFIG: &str = "bicycle.toml";
+
 mod build;
-pub use build::build;
+pub use build::{build, build_with_options, server_config_args, BuildOptions};
+
+mod dev;
+pub use dev::dev;
+
+mod evolution;
+mod migrations;
+
+mod init;
+pub use init::init;
+
+mod restore;
+pub use restore::restore;
+
+mod migrate;
+pub use migrate::migrate_engine;
+
+mod client;
+pub use client::{ConnectOptions, PackageChannel};
 
 pub(crate) mod gen;
+pub(crate) mod printer;
+pub(crate) mod template;
 pub(crate) mod utils;
@@ -17,13 +17,18 @@ You should have received a copy of the GNU Affero General Public License
 along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-use clap::{arg, command, value_parser};
+use clap::{arg, command, value_parser, ArgAction};
 
 use std::env;
 use std::fs;
+use std::io::{BufRead, BufWriter, Write};
 use std::process;
 
-use bicycle_proto::{biplane_client::BiplaneClient, Fn, OneOff, Stored};
+use bicycle::PackageChannel;
+use bicycle_proto::{
+    admin_client::AdminClient, biplane_client::BiplaneClient, ExportQuery, Fn, JsonLine, OneOff,
+    Stored,
+};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -32,6 +37,25 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .version(env!("CARGO_PKG_VERSION"))
         .subcommand_required(true)
         .arg_required_else_help(true)
+        .subcommand(
+            command!("init")
+                .arg_required_else_help(true)
+                .about("creates a new project with a schema.proto to start from.")
+                .arg(
+                    arg!(<DIR> "new or empty directory for the project")
+                        .value_parser(value_parser!(String)).required(true),
+                )
+                .arg(
+                    arg!(--"template" <TEMPLATE> "kind of project to create.")
+                        .value_parser(["server", "client", "offline", "sproc"])
+                        .default_value("client"),
+                )
+                .arg(
+                    arg!(--"engine" <ENGINE> "specifies database engine.")
+                        .value_parser(["rocksdb", "sqlite"])
+                        .default_value("rocksdb"),
+                ),
+        )
         .subcommand(
             command!("build")
             .arg_required_else_help(true)
@@ -44,6 +68,72 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     arg!(--"engine" <ENGINE> "specifies database engine.")
                         .value_parser(["rocksdb", "sqlite"])
                         .default_value("rocksdb"),
+                )
+                .arg(
+                    arg!(--"allow-breaking" "build even if the schema changed in ways that break stored records.")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--"package" <PACKAGE> "package to generate the proto in, defaults to the schema's package.")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"out-dir" <DIR> "directory to generate and build the server in, defaults to ./__bicycle__.")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"target" <TARGET> "target triple to build the server for, i.e. x86_64-unknown-linux-musl.")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"features" <FEATURES> "comma separated features to build the server with.")
+                        .value_parser(value_parser!(String))
+                        .value_delimiter(','),
+                )
+                .arg(
+                    arg!(--"profile" <PROFILE> "cargo profile to build the server with.")
+                        .value_parser(["debug", "release"])
+                        .default_value("release"),
+                ),
+        )
+        .subcommand(
+            command!("dev")
+                .arg_required_else_help(true)
+                .about("watches the schema, rebuilding and restarting a debug server on every change.")
+                .arg(
+                    arg!(<SCHEMA_PATH> "path to the schema.proto file")
+                        .value_parser(value_parser!(String)).required(true),
+                )
+                .arg(
+                    arg!(--"engine" <ENGINE> "specifies database engine.")
+                        .value_parser(["rocksdb", "sqlite"])
+                        .default_value("rocksdb"),
+                )
+                .arg(
+                    arg!(--"allow-breaking" "rebuild even if the schema changed in ways that break stored records.")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--"package" <PACKAGE> "package to generate the proto in, defaults to the schema's package.")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"out-dir" <DIR> "directory to generate and build the server in, defaults to ./__bicycle__.")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"target" <TARGET> "target triple to build the server for, i.e. x86_64-unknown-linux-musl.")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"features" <FEATURES> "comma separated features to build the server with.")
+                        .value_parser(value_parser!(String))
+                        .value_delimiter(','),
+                )
+                .arg(
+                    arg!(--"log" <LOG_LEVEL> "set the log level for the database server")
+                        .value_parser(["info", "warn", "error", "debug", "trace", "off"])
+                        .default_value("info"),
                 ),
         )
         .subcommand(
@@ -54,6 +144,187 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         .value_parser(["info", "warn", "error", "debug", "trace", "off"])
                         .default_value("info"),
                 )
+                .arg(
+                    arg!(--"out-dir" <DIR> "directory the server was built in, defaults to ./__bicycle__.")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"profile" <PROFILE> "cargo profile the server was built with.")
+                        .value_parser(["debug", "release"])
+                        .default_value("release"),
+                )
+                .arg(
+                    arg!(--"target" <TARGET> "target triple the server was built for.")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"config" <PATH> "server config file, defaults to ./bicycle.toml when it exists.")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"addr" <ADDRESS> "address for the server to listen at, defaults to [::0]:50051.")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"data-dir" <DIR> "directory the server keeps its database in.")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"sproc-dir" <DIR> "directory the server keeps deployed SPROCs in.")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"max-message-size" <BYTES> "largest message the server accepts or sends.")
+                        .value_parser(value_parser!(usize)),
+                )
+                .arg(
+                    arg!(--"concurrency-limit" <LIMIT> "requests the server handles at once per connection.")
+                        .value_parser(value_parser!(usize)),
+                )
+                .arg(
+                    arg!(--"max-concurrent-streams" <LIMIT> "HTTP/2 streams open at once per connection.")
+                        .value_parser(value_parser!(u32)),
+                )
+                .arg(
+                    arg!(--"tls-cert" <PATH> "PEM certificate for the server to serve TLS with.")
+                        .value_parser(value_parser!(String))
+                        .requires("tls-key"),
+                )
+                .arg(
+                    arg!(--"tls-key" <PATH> "PEM private key of --tls-cert.")
+                        .value_parser(value_parser!(String))
+                        .requires("tls-cert"),
+                )
+                .arg(
+                    arg!(--"tls-client-ca" <PATH> "PEM CA certificate that clients have to present a certificate signed by.")
+                        .value_parser(value_parser!(String)),
+                )
+        )
+        .subcommand(
+            command!("migrate-engine")
+                .arg_required_else_help(true)
+                .about("copies every record into another engine and verifies the copy.")
+                .arg(
+                    arg!(--"from" <ENGINE> "engine the server has been running on.")
+                        .value_parser(["rocksdb", "sqlite"]).required(true),
+                )
+                .arg(
+                    arg!(--"to" <ENGINE> "engine to copy records into.")
+                        .value_parser(["rocksdb", "sqlite"]).required(true),
+                )
+                .arg(
+                    arg!(--"out-dir" <DIR> "directory the server was built in, defaults to ./__bicycle__.")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"profile" <PROFILE> "cargo profile to build the migration with.")
+                        .value_parser(["debug", "release"])
+                        .default_value("release"),
+                )
+                .arg(
+                    arg!(--"target" <TARGET> "target triple to build the migration for.")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"config" <PATH> "server config file, defaults to ./bicycle.toml when it exists.")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"data-dir" <DIR> "directory the server keeps its database in.")
+                        .value_parser(value_parser!(String)),
+                )
+        )
+        .subcommand(
+            command!("backup")
+                .arg_required_else_help(true)
+                .about("takes an online backup of a running server.")
+                .arg(
+                    arg!(--"addr" <ADDRESS> "address of the database (i.e http://0.0.0.0::50051)")
+                        .value_parser(value_parser!(String)).required(true),
+                )
+                .arg(
+                    arg!(--"package" <PACKAGE> "package the server was built with.")
+                        .value_parser(value_parser!(String))
+                        .default_value(bicycle::DEFAULT_PACKAGE),
+                )
+                .args(connect_args())
+                .arg(
+                    arg!(--"out" <PATH> "path to write the backup archive to.")
+                        .value_parser(value_parser!(String)).required(true),
+                )
+        )
+        .subcommand(
+            command!("restore")
+                .arg_required_else_help(true)
+                .about("restores a backup archive into a new directory.")
+                .arg(
+                    arg!(<ARCHIVE_PATH> "path to the backup archive")
+                        .value_parser(value_parser!(String)).required(true),
+                )
+                .arg(
+                    arg!(--"dir" <DIR> "new or empty directory to restore into.")
+                        .value_parser(value_parser!(String)).required(true),
+                )
+                .arg(
+                    arg!(--"out-dir" <DIR> "directory the server was built in, defaults to ./__bicycle__.")
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    arg!(--"profile" <PROFILE> "cargo profile the server was built with.")
+                        .value_parser(["debug", "release"])
+                        .default_value("release"),
+                )
+                .arg(
+                    arg!(--"target" <TARGET> "target triple the server was built for.")
+                        .value_parser(value_parser!(String)),
+                )
+        )
+        .subcommand(
+            command!("export")
+                .arg_required_else_help(true)
+                .about("exports records as JSON using the canonical protobuf JSON mapping.")
+                .arg(
+                    arg!(--"addr" <ADDRESS> "address of the database (i.e http://0.0.0.0::50051)")
+                        .value_parser(value_parser!(String)).required(true),
+                )
+                .arg(
+                    arg!(--"package" <PACKAGE> "package the server was built with.")
+                        .value_parser(value_parser!(String))
+                        .default_value(bicycle::DEFAULT_PACKAGE),
+                )
+                .args(connect_args())
+                .arg(
+                    arg!(--"model" <MODEL> "model to export, can be repeated. defaults to every model.")
+                        .value_parser(value_parser!(String)).action(ArgAction::Append),
+                )
+                .arg(
+                    arg!(--"format" <FORMAT> "one record per line, or a single JSON array.")
+                        .value_parser(["ndjson", "json"])
+                        .default_value("ndjson"),
+                )
+                .arg(
+                    arg!(--"out" <PATH> "path to write the export to, defaults to stdout.")
+                        .value_parser(value_parser!(String)),
+                )
+        )
+        .subcommand(
+            command!("import")
+                .arg_required_else_help(true)
+                .about("imports records from a `bicycle export` file, either format.")
+                .arg(
+                    arg!(<PATH> "path to the export file")
+                        .value_parser(value_parser!(String)).required(true),
+                )
+                .arg(
+                    arg!(--"addr" <ADDRESS> "address of the database (i.e http://0.0.0.0::50051)")
+                        .value_parser(value_parser!(String)).required(true),
+                )
+                .arg(
+                    arg!(--"package" <PACKAGE> "package the server was built with.")
+                        .value_parser(value_parser!(String))
+                        .default_value(bicycle::DEFAULT_PACKAGE),
+                )
+                .args(connect_args())
         )
         .subcommand(
             command!("fn")
@@ -68,6 +339,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                             arg!(--"addr" <ADDRESS> "address of the database (i.e http://0.0.0.0::50051)")
                                 .value_parser(value_parser!(String)).required(true),
                         )
+                        .arg(
+                            arg!(--"package" <PACKAGE> "package the server was built with.")
+                                .value_parser(value_parser!(String))
+                                .default_value(bicycle::DEFAULT_PACKAGE),
+                        )
+                        .args(connect_args())
                         .arg(
                             arg!(--"lang" <LANGUAGE> "language to be compiled to WebAssembly.")
                                 .value_parser(["rust"]).required(true),
@@ -90,6 +367,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                             arg!(--"addr" <ADDRESS> "address of the database (i.e http://0.0.0.0::50051)")
                                 .value_parser(value_parser!(String)).required(true),
                         )
+                        .arg(
+                            arg!(--"package" <PACKAGE> "package the server was built with.")
+                                .value_parser(value_parser!(String))
+                                .default_value(bicycle::DEFAULT_PACKAGE),
+                        )
+                        .args(connect_args())
                         .arg(
                             arg!(--"name" <NAME> "name of stored procedure.")
                                 .value_parser(value_parser!(String)).required_unless_present("path"),
@@ -112,6 +395,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let matches = cmd.get_matches();
 
     match matches.subcommand() {
+        Some(("init", matches)) => {
+            let dir = matches.get_one::<String>("DIR").expect("required");
+
+            let template = matches
+                .get_one::<String>("template")
+                .expect("default value provided");
+            let engine = matches
+                .get_one::<String>("engine")
+                .expect("default value provided");
+
+            bicycle::init(dir, template, engine)?;
+
+            println!("✅ created {} project in {}", template, dir);
+
+            if template == "server" {
+                println!(
+                    "\n🛠️  build with `bicycle build schema.proto --engine {}`\n🚀 then start with `bicycle start`",
+                    engine
+                );
+            } else {
+                println!("\n📖 see {}/README.md to build and run it", dir);
+            }
+        }
         Some(("build", matches)) => {
             let schema_path = matches.get_one::<String>("SCHEMA_PATH").expect("required");
 
@@ -119,16 +425,94 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .get_one::<String>("engine")
                 .expect("default value provided");
 
-            bicycle::build(schema_path, engine)?;
+            let options = bicycle::BuildOptions {
+                allow_breaking: matches.get_flag("allow-breaking"),
+                package: matches.get_one::<String>("package").cloned(),
+                debug: matches.get_one::<String>("profile").expect("default value provided") == "debug",
+                out_dir: matches.get_one::<String>("out-dir").cloned(),
+                target: matches.get_one::<String>("target").cloned(),
+                features: matches
+                    .get_many::<String>("features")
+                    .map(|features| features.cloned().collect())
+                    .unwrap_or_default(),
+            };
+
+            bicycle::build_with_options(schema_path, engine, &options)?;
+        }
+        Some(("dev", matches)) => {
+            let schema_path = matches.get_one::<String>("SCHEMA_PATH").expect("required");
+
+            let engine = matches
+                .get_one::<String>("engine")
+                .expect("default value provided");
+            let log = matches
+                .get_one::<String>("log")
+                .expect("default value provided");
+
+            let options = bicycle::BuildOptions {
+                allow_breaking: matches.get_flag("allow-breaking"),
+                package: matches.get_one::<String>("package").cloned(),
+                out_dir: matches.get_one::<String>("out-dir").cloned(),
+                target: matches.get_one::<String>("target").cloned(),
+                features: matches
+                    .get_many::<String>("features")
+                    .map(|features| features.cloned().collect())
+                    .unwrap_or_default(),
+                ..Default::default()
+            };
+
+            bicycle::dev(schema_path, engine, &options, log)?;
         }
         Some(("start", matches)) => {
             let log = matches
                 .get_one::<String>("log")
                 .expect("default value provided");
 
-            env::set_current_dir("./__bicycle__")?;
+            let options = bicycle::BuildOptions {
+                debug: matches.get_one::<String>("profile").expect("default value provided") == "debug",
+                out_dir: matches.get_one::<String>("out-dir").cloned(),
+                target: matches.get_one::<String>("target").cloned(),
+                ..Default::default()
+            };
+
+            let mut server_args = vec![];
 
-            let mut child = process::Command::new("./target/release/bicycle_server")
+            // paths are resolved here since the server runs in the output directory
+            match matches.get_one::<String>("config") {
+                Some(config) => server_args.extend([
+                    "--config".to_string(),
+                    std::path::absolute(config)?.to_string_lossy().to_string(),
+                ]),
+                None => server_args.extend(bicycle::server_config_args()?),
+            }
+
+            for name in ["data-dir", "sproc-dir", "tls-cert", "tls-key", "tls-client-ca"] {
+                if let Some(path) = matches.get_one::<String>(name) {
+                    server_args.extend([
+                        format!("--{}", name),
+                        std::path::absolute(path)?.to_string_lossy().to_string(),
+                    ]);
+                }
+            }
+
+            if let Some(addr) = matches.get_one::<String>("addr") {
+                server_args.extend(["--addr".to_string(), addr.to_string()]);
+            }
+
+            for name in ["max-message-size", "concurrency-limit"] {
+                if let Some(limit) = matches.get_one::<usize>(name) {
+                    server_args.extend([format!("--{}", name), limit.to_string()]);
+                }
+            }
+
+            if let Some(limit) = matches.get_one::<u32>("max-concurrent-streams") {
+                server_args.extend(["--max-concurrent-streams".to_string(), limit.to_string()]);
+            }
+
+            env::set_current_dir(options.out_dir())?;
+
+            let mut child = process::Command::new(options.server_path())
+                .args(server_args)
                 .stdout(process::Stdio::piped())
                 .env("RUST_LOG", log)
                 .spawn()?;
@@ -139,12 +523,210 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             child.wait()?;
         }
+        Some(("migrate-engine", matches)) => {
+            let from = matches.get_one::<String>("from").expect("required");
+            let to = matches.get_one::<String>("to").expect("required");
+
+            println!("🚚 migrating from {} to {}...", from, to);
+            let now = std::time::Instant::now();
+
+            let options = bicycle::BuildOptions {
+                debug: matches.get_one::<String>("profile").expect("default value provided") == "debug",
+                out_dir: matches.get_one::<String>("out-dir").cloned(),
+                target: matches.get_one::<String>("target").cloned(),
+                ..Default::default()
+            };
+
+            let mut migrate_args = vec![];
+
+            // the migration runs in the output directory like the server, so it
+            // gets the same absolute config and data directory
+            match matches.get_one::<String>("config") {
+                Some(config) => migrate_args.extend([
+                    "--config".to_string(),
+                    std::path::absolute(config)?.to_string_lossy().to_string(),
+                ]),
+                None => migrate_args.extend(bicycle::server_config_args()?),
+            }
+
+            if let Some(data_dir) = matches.get_one::<String>("data-dir") {
+                migrate_args.extend([
+                    "--data-dir".to_string(),
+                    std::path::absolute(data_dir)?.to_string_lossy().to_string(),
+                ]);
+            }
+
+            bicycle::migrate_engine(from, to, &migrate_args, &options)?;
+
+            println!(
+                "✅ done!\n⏱️  migrated and verified in {}ms\n🔁 rebuild with `--engine {}` to serve it",
+                now.elapsed().as_millis(),
+                to
+            );
+        }
+        Some(("backup", matches)) => {
+            let addr = matches
+                .get_one::<String>("addr")
+                .expect("required")
+                .to_string();
+            let package = matches
+                .get_one::<String>("package")
+                .expect("default value provided");
+            let options = connect_options(matches)?;
+            let out = matches.get_one::<String>("out").expect("required");
+
+            println!("💾 backing up...");
+            let now = std::time::Instant::now();
+
+            let mut client = AdminClient::new(
+                PackageChannel::connect_with_options(addr, package, &options).await?,
+            );
+            let mut stream = client.backup(tonic::Request::new(())).await?.into_inner();
+
+            let mut file = fs::File::create(out)?;
+
+            loop {
+                match stream.message().await {
+                    Ok(Some(chunk)) => file.write_all(&chunk.data)?,
+                    Ok(None) => break,
+                    Err(status) => {
+                        // don't leave a truncated archive behind
+                        fs::remove_file(out)?;
+                        return Err(status.into());
+                    }
+                }
+            }
+
+            file.sync_all()?;
+
+            println!("✅ done!\n⏱️  backed up in {}ms", now.elapsed().as_millis());
+        }
+        Some(("restore", matches)) => {
+            let archive_path = matches.get_one::<String>("ARCHIVE_PATH").expect("required");
+            let dir = matches.get_one::<String>("dir").expect("required");
+
+            let options = bicycle::BuildOptions {
+                debug: matches.get_one::<String>("profile").expect("default value provided") == "debug",
+                out_dir: matches.get_one::<String>("out-dir").cloned(),
+                target: matches.get_one::<String>("target").cloned(),
+                ..Default::default()
+            };
+
+            let manifest = bicycle::restore(archive_path, dir, &options)?;
+
+            println!(
+                "✅ restored {} backup at seq {} into {}",
+                manifest.engine, manifest.seq, dir
+            );
+        }
+        Some(("export", matches)) => {
+            let addr = matches
+                .get_one::<String>("addr")
+                .expect("required")
+                .to_string();
+            let package = matches
+                .get_one::<String>("package")
+                .expect("default value provided");
+            let options = connect_options(matches)?;
+            let models = matches
+                .get_many::<String>("model")
+                .map(|models| models.cloned().collect())
+                .unwrap_or_default();
+            let json_array = matches
+                .get_one::<String>("format")
+                .expect("default value provided")
+                == "json";
+
+            let mut out: Box<dyn Write> = match matches.get_one::<String>("out") {
+                Some(path) => Box::new(BufWriter::new(fs::File::create(path)?)),
+                None => Box::new(BufWriter::new(std::io::stdout())),
+            };
+
+            let mut client = AdminClient::new(
+                PackageChannel::connect_with_options(addr, package, &options).await?,
+            );
+            let mut stream = client
+                .export(tonic::Request::new(ExportQuery { models }))
+                .await?
+                .into_inner();
+
+            let mut count = 0;
+
+            if json_array {
+                write!(out, "[")?;
+            }
+
+            while let Some(JsonLine { line }) = stream.message().await? {
+                if json_array {
+                    write!(out, "{}\n  {}", if count == 0 { "" } else { "," }, line)?;
+                } else {
+                    writeln!(out, "{}", line)?;
+                }
+
+                count += 1;
+            }
+
+            if json_array {
+                writeln!(out, "{}]", if count == 0 { "" } else { "\n" })?;
+            }
+
+            out.flush()?;
+
+            // stdout may be the export itself
+            eprintln!("✅ exported {} records", count);
+        }
+        Some(("import", matches)) => {
+            let path = matches.get_one::<String>("PATH").expect("required");
+            let addr = matches
+                .get_one::<String>("addr")
+                .expect("required")
+                .to_string();
+            let package = matches
+                .get_one::<String>("package")
+                .expect("default value provided");
+            let options = connect_options(matches)?;
+
+            let mut reader = std::io::BufReader::new(fs::File::open(path)?);
+
+            let lines: Box<dyn Iterator<Item = String> + Send> =
+                if reader.fill_buf()?.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'[') {
+                    let records: Vec<serde_json::Value> = serde_json::from_reader(reader)?;
+                    Box::new(records.into_iter().map(|record| record.to_string()))
+                } else {
+                    let lines = reader.lines().collect::<Result<Vec<_>, _>>()?;
+                    Box::new(lines.into_iter())
+                };
+
+            println!("📦 importing...");
+            let now = std::time::Instant::now();
+
+            let mut client = AdminClient::new(
+                PackageChannel::connect_with_options(addr, package, &options).await?,
+            );
+            let summary = client
+                .import(tonic::Request::new(tokio_stream::iter(
+                    lines.map(|line| JsonLine { line }),
+                )))
+                .await?
+                .into_inner();
+
+            println!(
+                "✅ done!\n⏱️  imported {} records, {} failed, in {}ms",
+                summary.written,
+                summary.failed,
+                now.elapsed().as_millis()
+            );
+        }
         Some(("fn", matches)) => match matches.subcommand() {
             Some(("deploy", matches)) => {
                 let addr = matches
                     .get_one::<String>("addr")
                     .expect("required")
                     .to_string();
+                let package = matches
+                    .get_one::<String>("package")
+                    .expect("default value provided");
+                let options = connect_options(matches)?;
                 let lang = matches
                     .get_one::<String>("lang")
                     .expect("required")
@@ -175,7 +757,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         println!("🕸️  compiled to WebAssembly.");
 
                         println!("📦 deploying procedure...");
-                        let mut client = BiplaneClient::connect(addr).await?;
+                        let mut client = BiplaneClient::new(
+                            PackageChannel::connect_with_options(addr, package, &options).await?,
+                        );
 
                         let request = tonic::Request::new(Fn {
                             name: name.to_string(),
@@ -194,6 +778,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .get_one::<String>("addr")
                     .expect("required")
                     .to_string();
+                let package = matches
+                    .get_one::<String>("package")
+                    .expect("default value provided");
+                let options = connect_options(matches)?;
 
                 let args = match matches.get_one::<String>("args") {
                     Some(args) => {
@@ -206,7 +794,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     None => prost_types::Value { kind: None },
                 };
 
-                let mut client = BiplaneClient::connect(addr).await?;
+                let mut client = BiplaneClient::new(
+                    PackageChannel::connect_with_options(addr, package, &options).await?,
+                );
 
                 let response = if let Some(name) = matches.get_one::<String>("name") {
                     let name = name.to_string();
@@ -329,3 +919,44 @@ pub fn proto_to_json(proto: prost_types::Value) -> serde_json::Value {
         serde_json::Value::Null
     }
 }
+
+/// flags for connecting to a server over TLS and authenticating with it.
+fn connect_args() -> [clap::Arg; 5] {
+    [
+        arg!(--"ca-cert" <PATH> "PEM CA certificate to verify an https:// server with, defaults to the system's roots.")
+            .value_parser(value_parser!(String)),
+        arg!(--"cert" <PATH> "PEM client certificate, for servers that verify clients.")
+            .value_parser(value_parser!(String))
+            .requires("key"),
+        arg!(--"key" <PATH> "PEM private key of --cert.")
+            .value_parser(value_parser!(String))
+            .requires("cert"),
+        arg!(--"domain" <DOMAIN> "name to verify the server's certificate against, defaults to the address's host.")
+            .value_parser(value_parser!(String)),
+        arg!(--"token" <TOKEN> "API token or JWT for servers with auth enabled.")
+            .value_parser(value_parser!(String))
+            .env("BICYCLE_TOKEN")
+            .hide_env_values(true),
+    ]
+}
+
+/// options from `connect_args`, with paths made absolute since `fn` commands
+/// change directory before connecting.
+fn connect_options(
+    matches: &clap::ArgMatches,
+) -> Result<bicycle::ConnectOptions, Box<dyn std::error::Error>> {
+    let path = |name: &str| -> Result<Option<String>, Box<dyn std::error::Error>> {
+        match matches.get_one::<String>(name) {
+            Some(path) => Ok(Some(std::path::absolute(path)?.to_string_lossy().to_string())),
+            None => Ok(None),
+        }
+    };
+
+    Ok(bicycle::ConnectOptions {
+        ca_cert: path("ca-cert")?,
+        cert: path("cert")?,
+        key: path("key")?,
+        domain: matches.get_one::<String>("domain").cloned(),
+        token: matches.get_one::<String>("token").cloned(),
+    })
+}
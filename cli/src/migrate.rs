@@ -0,0 +1,85 @@
+/*
+BicycleDB is a protobuf-defined database management system.
+
+Copyright (C) 2024 Ordinary Labs
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Instant;
+
+use crate::BuildOptions;
+
+/// copies every record from one engine's store in the server's data
+/// directory into the other's, then verifies record counts and
+/// checksums per model. the server should be stopped while this runs.
+///
+/// * `from` - the engine the server has been running on ("sqlite" or "rocksdb")
+/// * `to` - the engine to copy into, which must not have records yet
+/// * `args` - `--config` and `--data-dir` arguments locating the server's
+///   store, passed as they would be to the server
+/// * `options` - the output directory, profile and target the server was
+///   built with
+pub fn migrate_engine(
+    from: &str,
+    to: &str,
+    args: &[String],
+    options: &BuildOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if from == to {
+        return Err(format!("can't migrate {} into itself", to).into());
+    }
+
+    let out_dir = options.out_dir();
+
+    if !Path::new(out_dir).exists() {
+        return Err(format!("no {} directory, run `bicycle build` first", out_dir).into());
+    }
+
+    let now = Instant::now();
+    println!("🛠️  building migration...");
+
+    let out = Command::new("cargo")
+        .args(options.cargo_args("bicycle_migrate"))
+        .current_dir(out_dir)
+        .stderr(Stdio::piped())
+        .output()?;
+
+    if !out.status.success() {
+        return Err(format!(
+            "failed to build migration: {}",
+            String::from_utf8(out.stderr)?
+        )
+        .into());
+    }
+
+    println!(
+        "🛠️  done building migration. [{}ms]",
+        now.elapsed().as_millis()
+    );
+
+    let status = Command::new(options.bin_path("bicycle_migrate"))
+        .args([from, to])
+        .args(args)
+        .current_dir(out_dir)
+        .status()?;
+
+    if !status.success() {
+        return Err(format!("failed to migrate from {} to {}", from, to).into());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,118 @@
+/*
+BicycleDB is a protobuf-defined database management system.
+
+Copyright (C) 2024 Ordinary Labs
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+pub(crate) const MIGRATIONS_DIR: &str = "migrations";
+
+/// `0003_split_name.wasm` and `0003_split_name/` are both version 3.
+fn parse_version(name: &str) -> Option<u64> {
+    let digits: String = name.chars().take_while(|c| c.is_ascii_digit()).collect();
+
+    match name[digits.len()..].chars().next() {
+        None | Some('_') | Some('.') => digits.parse().ok().filter(|version| *version > 0),
+        _ => None,
+    }
+}
+
+fn compile_migration(path: &Path) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    if path.is_file() {
+        return Ok(fs::read(path)?);
+    }
+
+    let status = Command::new("cargo")
+        .args(["wasi", "build", "--release"])
+        .current_dir(path)
+        .status()?;
+
+    if !status.success() {
+        return Err(format!("failed to compile migration '{}'", path.display()).into());
+    }
+
+    Ok(fs::read(
+        path.join("target/wasm32-wasi/release/biplane_function.wasm"),
+    )?)
+}
+
+/// compiles the migrations in `migrations/` next to the schema into
+/// `__bicycle__/migrations`, named by version. each is a Biplane function,
+/// either a `.wasm` file or a crate built with `cargo wasi`. returns their
+/// versions in order, the last of which is the schema version.
+///
+/// * `schema_path` - path to the schema.proto file
+/// * `out_dir` - directory the server is generated in
+pub(crate) fn collect_migrations(
+    schema_path: &str,
+    out_dir: &str,
+) -> Result<Vec<u64>, Box<dyn std::error::Error>> {
+    let src = Path::new(schema_path)
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(MIGRATIONS_DIR);
+    let dest = Path::new(out_dir).join(MIGRATIONS_DIR);
+
+    if dest.exists() {
+        fs::remove_dir_all(&dest)?;
+    }
+
+    if !src.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut migrations = vec![];
+
+    for entry in fs::read_dir(&src)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+
+        if name.starts_with('.') || (path.is_file() && !name.ends_with(".wasm")) {
+            continue;
+        }
+
+        match parse_version(name) {
+            Some(version) => migrations.push((version, path)),
+            None => eprintln!("skipping migration '{}', names start with a version", name),
+        }
+    }
+
+    migrations.sort();
+
+    for pair in migrations.windows(2) {
+        if pair[0].0 == pair[1].0 {
+            return Err(format!("more than one migration for version {}", pair[0].0).into());
+        }
+    }
+
+    fs::create_dir_all(&dest)?;
+
+    for (version, path) in migrations.iter() {
+        println!("🦀 compiling migration {}...", version);
+        fs::write(
+            dest.join(format!("{}.wasm", version)),
+            compile_migration(path)?,
+        )?;
+    }
+
+    Ok(migrations.iter().map(|(version, _)| *version).collect())
+}
@@ -0,0 +1,414 @@
+/*
+BicycleDB is a protobuf-defined database management system.
+
+Copyright (C) 2024 Ordinary Labs
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use prost_types::{
+    field_descriptor_proto::Label, DescriptorProto, EnumDescriptorProto, FieldDescriptorProto,
+};
+
+use crate::utils::{get_usable_type, is_map_entry, TypeScope};
+
+/// the highest field number, printed as `max` in reserved ranges.
+const MAX_FIELD_NUMBER: i32 = 536_870_911;
+
+fn indent(depth: usize) -> String {
+    "  ".repeat(depth)
+}
+
+/// `json_name` protoc derives from a field name when none is given.
+fn default_json_name(name: &str) -> String {
+    let mut json_name = String::new();
+    let mut capitalize = false;
+
+    for c in name.chars() {
+        if c == '_' {
+            capitalize = true;
+        } else if capitalize {
+            json_name.push(c.to_ascii_uppercase());
+            capitalize = false;
+        } else {
+            json_name.push(c);
+        }
+    }
+
+    json_name
+}
+
+fn print_options(options: Vec<String>) -> String {
+    if options.is_empty() {
+        "".to_string()
+    } else {
+        format!(" [{}]", options.join(", "))
+    }
+}
+
+fn print_field(
+    field: &FieldDescriptorProto,
+    message: &DescriptorProto,
+    scope: &TypeScope,
+    depth: usize,
+) -> String {
+    let _type = get_usable_type(field, message, scope);
+
+    let label = match field.label() {
+        Label::Repeated if !_type.starts_with("map<") => "repeated ",
+        _ if field.proto3_optional() => "optional ",
+        _ => "",
+    };
+
+    let mut options = vec![];
+
+    if let Some(field_options) = field.options.as_ref() {
+        if field_options.ctype.is_some() {
+            options.push(format!("ctype = {}", field_options.ctype().as_str_name()));
+        }
+        if let Some(packed) = field_options.packed {
+            options.push(format!("packed = {}", packed));
+        }
+        if field_options.jstype.is_some() {
+            options.push(format!("jstype = {}", field_options.jstype().as_str_name()));
+        }
+        if let Some(lazy) = field_options.lazy {
+            options.push(format!("lazy = {}", lazy));
+        }
+        if let Some(deprecated) = field_options.deprecated {
+            options.push(format!("deprecated = {}", deprecated));
+        }
+    }
+
+    // protoc fills in `json_name` for every field
+    if field.json_name.is_some() && field.json_name() != default_json_name(field.name()) {
+        options.push(format!("json_name = \"{}\"", field.json_name()));
+    }
+
+    format!(
+        "{}{}{} {} = {}{};",
+        indent(depth),
+        label,
+        _type,
+        field.name(),
+        field.number(),
+        print_options(options)
+    )
+}
+
+/// `ranges` are inclusive.
+fn print_reserved(ranges: Vec<(i32, i32)>, names: &[String], max: i32, depth: usize) -> String {
+    let mut lines = vec![];
+
+    if !ranges.is_empty() {
+        let ranges: Vec<String> = ranges
+            .iter()
+            .map(|(start, end)| match *end {
+                end if end == *start => start.to_string(),
+                end if end == max => format!("{} to max", start),
+                end => format!("{} to {}", start, end),
+            })
+            .collect();
+
+        lines.push(format!("{}reserved {};", indent(depth), ranges.join(", ")));
+    }
+
+    if !names.is_empty() {
+        let names: Vec<String> = names.iter().map(|name| format!("\"{}\"", name)).collect();
+        lines.push(format!("{}reserved {};", indent(depth), names.join(", ")));
+    }
+
+    lines.join("\n")
+}
+
+/// options, fields, reserved numbers and names, enums and nested messages of
+/// `message`, each group separated by a blank line.
+fn print_message_body(
+    message: &DescriptorProto,
+    scope: &TypeScope,
+    depth: usize,
+) -> String {
+    let mut sections: Vec<String> = vec![];
+
+    if let Some(deprecated) = message.options.as_ref().and_then(|o| o.deprecated) {
+        sections.push(format!(
+            "{}option deprecated = {};",
+            indent(depth),
+            deprecated
+        ));
+    }
+
+    let mut fields: Vec<String> = vec![];
+    let mut oneofs: Vec<i32> = vec![];
+
+    for field in message.field.iter() {
+        match field.oneof_index {
+            // proto3 `optional` fields are each wrapped in a synthetic oneof
+            Some(index) if !field.proto3_optional() => {
+                // members of a oneof are printed together, where its first member is
+                if oneofs.contains(&index) {
+                    continue;
+                }
+
+                oneofs.push(index);
+
+                let mut lines = vec![format!(
+                    "{}oneof {} {{",
+                    indent(depth),
+                    message.oneof_decl[index as usize].name()
+                )];
+
+                for member in message.field.iter() {
+                    if member.oneof_index == Some(index) {
+                        lines.push(print_field(member, message, scope, depth + 1));
+                    }
+                }
+
+                lines.push(format!("{}}}", indent(depth)));
+                fields.push(lines.join("\n"));
+            }
+            _ => fields.push(print_field(field, message, scope, depth)),
+        }
+    }
+
+    if !fields.is_empty() {
+        sections.push(fields.join("\n"));
+    }
+
+    let reserved = print_reserved(
+        message
+            .reserved_range
+            .iter()
+            .map(|range| (range.start(), range.end() - 1))
+            .collect(),
+        &message.reserved_name,
+        MAX_FIELD_NUMBER,
+        depth,
+    );
+
+    if !reserved.is_empty() {
+        sections.push(reserved);
+    }
+
+    for enum_type in message.enum_type.iter() {
+        sections.push(print_enum(enum_type, depth));
+    }
+
+    for nested in message.nested_type.iter() {
+        // printed as `map<,>` fields
+        if is_map_entry(nested) {
+            continue;
+        }
+
+        sections.push(print_message(nested, scope, depth));
+    }
+
+    sections.join("\n\n")
+}
+
+pub(crate) fn print_message(message: &DescriptorProto, scope: &TypeScope, depth: usize) -> String {
+    let body = print_message_body(message, scope, depth + 1);
+
+    if body.is_empty() {
+        return format!("{}message {} {{}}", indent(depth), message.name());
+    }
+
+    format!(
+        "{}message {} {{\n{}\n{}}}",
+        indent(depth),
+        message.name(),
+        body,
+        indent(depth)
+    )
+}
+
+pub(crate) fn print_enum(enum_type: &EnumDescriptorProto, depth: usize) -> String {
+    let mut lines = vec![format!("{}enum {} {{", indent(depth), enum_type.name())];
+
+    if let Some(options) = enum_type.options.as_ref() {
+        if let Some(allow_alias) = options.allow_alias {
+            lines.push(format!(
+                "{}option allow_alias = {};",
+                indent(depth + 1),
+                allow_alias
+            ));
+        }
+        if let Some(deprecated) = options.deprecated {
+            lines.push(format!(
+                "{}option deprecated = {};",
+                indent(depth + 1),
+                deprecated
+            ));
+        }
+    }
+
+    for value in enum_type.value.iter() {
+        let mut options = vec![];
+
+        if let Some(deprecated) = value.options.as_ref().and_then(|o| o.deprecated) {
+            options.push(format!("deprecated = {}", deprecated));
+        }
+
+        lines.push(format!(
+            "{}{} = {}{};",
+            indent(depth + 1),
+            value.name(),
+            value.number(),
+            print_options(options)
+        ));
+    }
+
+    let reserved = print_reserved(
+        enum_type
+            .reserved_range
+            .iter()
+            .map(|range| (range.start(), range.end()))
+            .collect(),
+        &enum_type.reserved_name,
+        i32::MAX,
+        depth + 1,
+    );
+
+    if !reserved.is_empty() {
+        lines.push(format!("\n{}", reserved));
+    }
+
+    lines.push(format!("{}}}", indent(depth)));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use std::process::Command;
+
+    use prost::Message;
+    use prost_types::{FileDescriptorProto, FileDescriptorSet};
+
+    use super::*;
+    use crate::utils::{is_well_known, schema_file};
+
+    const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/cli/tests/golden");
+
+    fn compile(include: &Path, file: &str) -> FileDescriptorSet {
+        let out = env::temp_dir().join(format!(
+            "bicycle_golden_{}_{}.bin",
+            std::process::id(),
+            file.replace('/', "_")
+        ));
+
+        let status = Command::new(env::var("PROTOC").unwrap_or("protoc".to_string()))
+            .arg("--include_imports")
+            .arg("-I")
+            .arg(include)
+            .arg("-o")
+            .arg(&out)
+            .arg(file)
+            .status()
+            .expect("failed to run protoc");
+
+        assert!(status.success(), "protoc failed to compile {}", file);
+
+        let bytes = fs::read(&out).unwrap();
+        fs::remove_file(&out).unwrap();
+
+        FileDescriptorSet::decode(&bytes[..]).unwrap()
+    }
+
+    /// every message and enum of the schema and the files it imports, the way
+    /// `gen` flattens them into the generated proto.
+    fn print_flattened(file_descriptor_set: &FileDescriptorSet) -> String {
+        let scope = TypeScope::new(file_descriptor_set);
+        let mut printed = vec![];
+
+        for file in file_descriptor_set.file.iter() {
+            if is_well_known(file.name()) {
+                continue;
+            }
+
+            for message in file.message_type.iter() {
+                printed.push(print_message(message, &scope, 0));
+            }
+
+            for enum_type in file.enum_type.iter() {
+                printed.push(print_enum(enum_type, 0));
+            }
+        }
+
+        printed.join("\n\n") + "\n"
+    }
+
+    /// recompiles the printed proto and checks it declares the same types.
+    fn assert_round_trips(schema: &FileDescriptorProto, printed: &str) {
+        let dir = env::temp_dir().join(format!("bicycle_golden_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut header = format!("syntax = \"proto3\";\npackage {};\n", schema.package());
+
+        for import in schema.dependency.iter() {
+            header = format!("{}import \"{}\";\n", header, import);
+        }
+
+        fs::write(dir.join(schema.name()), format!("{}\n{}", header, printed)).unwrap();
+
+        let reprinted = compile(&dir, schema.name());
+        let reprinted = schema_file(&reprinted).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(schema.message_type, reprinted.message_type);
+        assert_eq!(schema.enum_type, reprinted.enum_type);
+    }
+
+    /// set `BICYCLE_BLESS=1` to rewrite the golden files.
+    #[test]
+    fn golden() {
+        let golden_dir = Path::new(GOLDEN_DIR);
+        let bless = env::var("BICYCLE_BLESS").is_ok();
+
+        let mut schemas: Vec<String> = fs::read_dir(golden_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| name.ends_with(".proto"))
+            .collect();
+
+        schemas.sort();
+        assert!(!schemas.is_empty());
+
+        for name in schemas {
+            let file_descriptor_set = compile(golden_dir, &name);
+            let printed = print_flattened(&file_descriptor_set);
+
+            let golden_path = golden_dir.join(name.replace(".proto", ".golden"));
+
+            if bless {
+                fs::write(&golden_path, &printed).unwrap();
+            }
+
+            let golden = fs::read_to_string(&golden_path)
+                .unwrap_or_else(|_| panic!("missing {}", golden_path.display()));
+
+            assert_eq!(golden, printed, "{} doesn't match its golden file", name);
+
+            // types from imported files are renamed into the schema's package
+            let schema = schema_file(&file_descriptor_set).unwrap();
+
+            if schema.dependency.iter().all(|import| is_well_known(import)) {
+                assert_round_trips(schema, &printed);
+            }
+        }
+    }
+}
@@ -0,0 +1,66 @@
+/*
+BicycleDB is a protobuf-defined database management system.
+
+Copyright (C) 2024 Ordinary Labs
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use bicycle_proto::BackupManifest;
+use prost::Message;
+
+use crate::BuildOptions;
+
+/// restores a backup taken with `bicycle backup`, using the built server so
+/// the archive is checked against the engine it was built with.
+///
+/// * `archive_path` - path to the backup archive
+/// * `dir` - new or empty directory to restore into, a server started from
+///   here or with `--data-dir` set to it picks up where the backup was taken
+/// * `options` - the output directory, profile and target the server was
+///   built with
+pub fn restore(
+    archive_path: &str,
+    dir: &str,
+    options: &BuildOptions,
+) -> Result<BackupManifest, Box<dyn std::error::Error>> {
+    let server_path = options.server_path();
+
+    if !Path::new(options.out_dir()).join(&server_path).exists() {
+        return Err(format!(
+            "no server built in {}, run `bicycle build` first",
+            options.out_dir()
+        )
+        .into());
+    }
+
+    // the server runs in the output directory, so paths are resolved here
+    let out = Command::new(server_path)
+        .arg("--restore")
+        .arg(std::path::absolute(archive_path)?)
+        .arg("--data-dir")
+        .arg(std::path::absolute(dir)?)
+        .current_dir(options.out_dir())
+        .stderr(Stdio::piped())
+        .output()?;
+
+    if !out.status.success() {
+        return Err(String::from_utf8(out.stderr)?.trim().into());
+    }
+
+    Ok(BackupManifest::decode(&out.stdout[..])?)
+}
@@ -0,0 +1,247 @@
+/*
+BicycleDB is a protobuf-defined database management system.
+
+Copyright (C) 2024 Ordinary Labs
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use heck::{ToShoutySnakeCase, ToSnakeCase};
+
+use crate::utils::Model;
+
+/// how the model name is written where the placeholder was.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Case {
+    /// `Example`
+    Pascal,
+    /// `example`
+    Snake,
+    /// `EXAMPLE`
+    Shouty,
+}
+
+const PLACEHOLDERS: [(&str, Case); 3] = [
+    ("Example", Case::Pascal),
+    ("example", Case::Snake),
+    ("EXAMPLE", Case::Shouty),
+];
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    Name(Case),
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// whether a placeholder can start after `prev`, i.e. `prev` ends a word of the
+/// identifier it's in. a `Pascal` placeholder also starts a word after a
+/// lowercase letter or digit, like in `GetExamplesByPk`.
+fn starts_word(prev: Option<char>, case: Case) -> bool {
+    match prev {
+        None => true,
+        Some(c) if !is_ident(c) || c == '_' => true,
+        Some(c) => case == Case::Pascal && (c.is_ascii_lowercase() || c.is_ascii_digit()),
+    }
+}
+
+/// whether a placeholder can end before `rest`, allowing for a plural `s`.
+fn ends_word(rest: &str, case: Case) -> bool {
+    let plural = if case == Case::Shouty { 'S' } else { 's' };
+    let rest = rest.strip_prefix(plural).unwrap_or(rest);
+
+    match rest.chars().next() {
+        None => true,
+        Some(c) if !is_ident(c) || c == '_' => true,
+        Some(c) => case == Case::Pascal && (c.is_ascii_uppercase() || c.is_ascii_digit()),
+    }
+}
+
+/// a block of the workspace source written against the `Example` model, with
+/// each whole-word `Example`, `example` or `EXAMPLE` as a placeholder for the
+/// name of the model it's rendered for. words that only contain the
+/// placeholder, like `counterexample`, are left as is.
+#[derive(Debug)]
+pub(crate) struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub(crate) fn parse(source: &str) -> Template {
+        let mut segments = vec![];
+        let mut text = String::new();
+        let mut prev: Option<char> = None;
+        let mut i = 0;
+
+        'scan: while i < source.len() {
+            let rest = &source[i..];
+
+            for (placeholder, case) in PLACEHOLDERS {
+                if rest.starts_with(placeholder)
+                    && starts_word(prev, case)
+                    && ends_word(&rest[placeholder.len()..], case)
+                {
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+
+                    segments.push(Segment::Name(case));
+
+                    prev = placeholder.chars().last();
+                    i += placeholder.len();
+
+                    continue 'scan;
+                }
+            }
+
+            let c = rest.chars().next().unwrap();
+
+            text.push(c);
+            prev = Some(c);
+            i += c.len_utf8();
+        }
+
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+
+        Template { segments }
+    }
+
+    pub(crate) fn render(&self, model: &Model) -> String {
+        let snake = model.name.to_snake_case();
+        let shouty = model.name.to_shouty_snake_case();
+
+        let mut rendered = String::new();
+
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Text(text) => rendered.push_str(text),
+                Segment::Name(Case::Pascal) => rendered.push_str(&model.name),
+                Segment::Name(Case::Snake) => rendered.push_str(&snake),
+                Segment::Name(Case::Shouty) => rendered.push_str(&shouty),
+            }
+        }
+
+        rendered
+    }
+
+    /// renders the template for each model, with `separator` between them.
+    pub(crate) fn render_all(&self, models: &[Model], separator: &str) -> String {
+        models
+            .iter()
+            .map(|model| self.render(model))
+            .collect::<Vec<String>>()
+            .join(separator)
+    }
+}
+
+/// line range of the block between the `##START_<name>##` and `##END_<name>##`
+/// marker lines of `source`, excluding the markers.
+fn block_range(source: &str, name: &str) -> (usize, usize) {
+    let start_marker = format!("##START_{}##", name);
+    let end_marker = format!("##END_{}##", name);
+
+    let mut start = None;
+    let mut offset = 0;
+
+    for line in source.split_inclusive('\n') {
+        if line.contains(&start_marker) {
+            start = Some(offset + line.len());
+        } else if line.contains(&end_marker) {
+            let start = start.unwrap_or_else(|| panic!("'{}' ends before it starts", name));
+            return (start, offset);
+        }
+
+        offset += line.len();
+    }
+
+    panic!("template has no '{}' block", name)
+}
+
+/// the lines between the `name` block's markers.
+pub(crate) fn block<'a>(source: &'a str, name: &str) -> &'a str {
+    let (start, end) = block_range(source, name);
+    &source[start..end]
+}
+
+/// `source` with the lines between the `name` block's markers replaced by
+/// `content`.
+pub(crate) fn splice(source: &str, name: &str, content: &str) -> String {
+    let (start, end) = block_range(source, name);
+    format!("{}{}{}", &source[..start], content, &source[end..])
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_types::DescriptorProto;
+
+    use super::*;
+
+    fn render(source: &str) -> String {
+        let model = Model {
+            name: "DogTreat".to_string(),
+            message: DescriptorProto::default(),
+            has_expires_at: false,
+        };
+
+        Template::parse(source).render(&model)
+    }
+
+    #[test]
+    fn parses_placeholders_within_identifiers() {
+        assert_eq!(
+            Template::parse("GetExamplesByPk").segments,
+            vec![
+                Segment::Text("Get".to_string()),
+                Segment::Name(Case::Pascal),
+                Segment::Text("sByPk".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn renders_each_case() {
+        assert_eq!(
+            render("Example example EXAMPLE"),
+            "DogTreat dog_treat DOG_TREAT"
+        );
+        assert_eq!(
+            render("Examples examples EXAMPLES"),
+            "DogTreats dog_treats DOG_TREATS"
+        );
+    }
+
+    #[test]
+    fn renders_words_of_identifiers() {
+        assert_eq!(render("ExampleSet"), "DogTreatSet");
+        assert_eq!(render("GetExamplesByPk"), "GetDogTreatsByPk");
+        assert_eq!(render("EXAMPLE_SET"), "DOG_TREAT_SET");
+        assert_eq!(render("EXAMPLES_OF"), "DOG_TREATS_OF");
+        assert_eq!(render("put_example(example)"), "put_dog_treat(dog_treat)");
+    }
+
+    #[test]
+    fn leaves_words_containing_the_placeholder() {
+        assert_eq!(render("counterexample"), "counterexample");
+        assert_eq!(render("Counterexample"), "Counterexample");
+        assert_eq!(
+            render("example.counterexample_count + example.exampled"),
+            "dog_treat.counterexample_count + dog_treat.exampled"
+        );
+    }
+}
@@ -17,109 +17,217 @@ You should have received a copy of the GNU Affero General Public License
 along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+use std::cmp::Reverse;
+
 use prost_types::{
     field_descriptor_proto::{self, Type},
-    DescriptorProto, FieldDescriptorProto,
+    DescriptorProto, EnumDescriptorProto, FieldDescriptorProto, FileDescriptorProto,
+    FileDescriptorSet,
 };
 
+/// well-known types are imported by the generated proto rather than declared
+/// in it.
+pub fn is_well_known(file_name: &str) -> bool {
+    file_name.starts_with("google/protobuf/")
+}
+
+/// the file being built, protoc lists the files it imports before it.
+pub fn schema_file(file_descriptor_set: &FileDescriptorSet) -> Option<&FileDescriptorProto> {
+    file_descriptor_set.file.last()
+}
+
+/// leading comment line that marks a message of the schema as a model.
+pub const MODEL_ANNOTATION: &str = "@bicycle.model";
+
+fn is_annotated(file: &FileDescriptorProto, index: usize) -> bool {
+    let Some(source_code_info) = file.source_code_info.as_ref() else {
+        return false;
+    };
+
+    // 4 is `FileDescriptorProto.message_type`
+    source_code_info
+        .location
+        .iter()
+        .filter(|location| location.path == [4, index as i32])
+        .any(|location| {
+            location
+                .leading_comments()
+                .lines()
+                .any(|line| line.trim() == MODEL_ANNOTATION)
+        })
+}
+
+pub fn has_valid_pk(message: &DescriptorProto) -> bool {
+    message
+        .field
+        .iter()
+        .any(|field| field.name() == "pk" && field.number() == 1 && field.r#type() == Type::String)
+}
+
+/// whether the top-level message at `index` of the schema is a model. once any
+/// message is annotated with `// @bicycle.model` only annotated messages are,
+/// otherwise every message with a `string pk = 1;` is.
+pub fn is_model(file: &FileDescriptorProto, index: usize) -> bool {
+    if (0..file.message_type.len()).any(|i| is_annotated(file, i)) {
+        is_annotated(file, index)
+    } else {
+        has_valid_pk(&file.message_type[index])
+    }
+}
+
+/// resolves type names to how they're referenced from the generated proto,
+/// which declares the types of the schema and the files it imports in its own
+/// package. well-known types keep their full name.
 #[derive(Debug)]
-pub struct Property {
-    pub _type: String,
-    pub name: String,
-    pub number: i32,
+pub struct TypeScope {
+    packages: Vec<String>,
+}
+
+impl TypeScope {
+    pub fn new(file_descriptor_set: &FileDescriptorSet) -> TypeScope {
+        let mut packages: Vec<String> = file_descriptor_set
+            .file
+            .iter()
+            .filter(|file| !is_well_known(file.name()))
+            .map(|file| file.package().to_string())
+            .collect();
+
+        packages.sort();
+        packages.dedup();
+
+        // longest first so nested packages win over their parents
+        packages.sort_by_key(|package| Reverse(package.len()));
+
+        TypeScope { packages }
+    }
+
+    pub fn resolve(&self, type_name: &str) -> String {
+        let type_name = type_name.trim_start_matches('.');
+
+        if type_name.starts_with("google.protobuf.") {
+            return type_name.to_string();
+        }
+
+        for package in self.packages.iter() {
+            if package.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = type_name
+                .strip_prefix(package.as_str())
+                .and_then(|name| name.strip_prefix('.'))
+            {
+                return name.to_string();
+            }
+        }
+
+        type_name.to_string()
+    }
+}
+
+/// what's generated from the schema and the files it imports.
+#[derive(Debug)]
+pub struct Schema {
+    pub models: Vec<Model>,
+    /// messages that aren't models, shared by them.
+    pub helpers: Vec<DescriptorProto>,
+    pub enums: Vec<EnumDescriptorProto>,
+    /// well-known type files the generated proto imports.
+    pub imports: Vec<String>,
+    pub scope: TypeScope,
+    /// package the generated proto declares its types and services in.
+    pub package: String,
+    /// versions of the migrations compiled into `migrations/`, in order.
+    pub migrations: Vec<u64>,
 }
 
 #[derive(Debug)]
 pub struct Model {
     pub name: String,
-    pub properties: Vec<Property>,
-    pub nested_models: Vec<Model>,
+    pub message: DescriptorProto,
+    pub has_expires_at: bool,
 }
 
 pub fn construct_model(
     message: &DescriptorProto,
     should_check_pk: bool,
 ) -> Result<Model, &'static str> {
-    let mut has_valid_pk = false;
-    let mut properties: Vec<Property> = vec![];
+    let mut has_expires_at = false;
 
     for field in message.field.iter() {
-        let repeated = match field.label() {
-            field_descriptor_proto::Label::Repeated => "repeated ",
-            _ => "",
-        };
-
-        properties.push(Property {
-            _type: format!("{}{}", repeated, get_usable_type(&field, &message)),
-            name: field.name().to_string(),
-            number: field.number(),
-        });
-
-        if field.name() == "pk" {
-            if field.number() == 1 {
-                match field.r#type() {
-                    Type::String => {
-                        has_valid_pk = true;
-                    }
-                    _ => eprintln!("missing 'string pk = 1;'"),
+        if field.name() == "pk" && field.number() == 1 && field.r#type() != Type::String {
+            eprintln!("missing 'string pk = 1;'");
+        }
+
+        if field.name() == "expires_at" {
+            match (field.r#type(), field.label()) {
+                (Type::Uint64, field_descriptor_proto::Label::Optional)
+                    if !field.proto3_optional() =>
+                {
+                    has_expires_at = true;
+                }
+                _ => {
+                    return Err("`expires_at` must be a `uint64` that is not optional or repeated")
                 }
             }
         }
     }
 
-    if should_check_pk && !has_valid_pk {
+    if should_check_pk && !has_valid_pk(message) {
         return Err("model does not include `string pk = 1;`");
     }
 
-    let mut nested_models: Vec<Model> = vec![];
-
-    for nested_message in message.nested_type.iter() {
-        if nested_message.name().ends_with("Entry") {
-            continue;
-        }
-
-        let nested_model = construct_model(&nested_message, false)?;
-        nested_models.push(nested_model);
-    }
-
     Ok(Model {
         name: message.name().to_string(),
-        properties,
-        nested_models,
+        message: message.clone(),
+        has_expires_at,
     })
 }
 
-pub fn get_complex_type(field: &FieldDescriptorProto, message: &DescriptorProto) -> String {
-    if let Some(type_name) = field.type_name().split('.').last() {
+/// `map<,>` fields are declared as a nested `*Entry` message.
+pub fn is_map_entry(message: &DescriptorProto) -> bool {
+    message
+        .options
+        .as_ref()
+        .map(|options| options.map_entry())
+        .unwrap_or(false)
+}
+
+pub fn get_complex_type(
+    field: &FieldDescriptorProto,
+    message: &DescriptorProto,
+    scope: &TypeScope,
+) -> String {
+    let type_name = field.type_name();
+
+    // check for map<,> type
+    if let Some(entry_name) = type_name.split('.').next_back() {
         for nested_type in message.nested_type.iter() {
-            if nested_type.name() == type_name {
-                // check for map<,> type
-                if type_name.ends_with("Entry") {
-                    let mut key_type = "".to_string();
-                    let mut val_type = "".to_string();
-
-                    for field in nested_type.field.iter() {
-                        if field.name() == "key" {
-                            key_type = get_usable_type(&field, &message);
-                        } else if field.name() == "value" {
-                            val_type = get_usable_type(&field, &message);
-                        }
+            if nested_type.name() == entry_name && is_map_entry(nested_type) {
+                let mut key_type = "".to_string();
+                let mut val_type = "".to_string();
+
+                for field in nested_type.field.iter() {
+                    if field.name() == "key" {
+                        key_type = get_usable_type(field, message, scope);
+                    } else if field.name() == "value" {
+                        val_type = get_usable_type(field, message, scope);
                     }
-
-                    return format!("map<{}, {}>", key_type, val_type);
-                } else {
-                    return type_name.to_string();
                 }
+
+                return format!("map<{}, {}>", key_type, val_type);
             }
         }
-
-        return "".to_string();
     }
 
-    "".to_string()
+    scope.resolve(type_name)
 }
 
-pub fn get_usable_type(field: &FieldDescriptorProto, message: &DescriptorProto) -> String {
+pub fn get_usable_type(
+    field: &FieldDescriptorProto,
+    message: &DescriptorProto,
+    scope: &TypeScope,
+) -> String {
     match field.r#type() {
         Type::Double => "double".to_string(),
         Type::Float => "float".to_string(),
@@ -136,10 +244,10 @@ pub fn get_usable_type(field: &FieldDescriptorProto, message: &DescriptorProto)
         Type::Bool => "bool".to_string(),
         Type::String => "string".to_string(),
         Type::Bytes => "bytes".to_string(),
-        Type::Message => get_complex_type(field, message),
+        Type::Message => get_complex_type(field, message, scope),
+        Type::Enum => scope.resolve(field.type_name()),
 
         // !! handle explicitly
-        Type::Enum => "ENUM".to_string(),
         Type::Group => "GROUP IS NOT SUPPORTED".to_string(),
     }
 }
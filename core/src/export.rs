@@ -0,0 +1,166 @@
+/*
+BicycleDB is a protobuf-defined database management system.
+
+Copyright (C) 2024 Ordinary Labs
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::error::Error;
+
+use lazy_static::lazy_static;
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor, SerializeOptions};
+use serde_json::{json, Value};
+
+use bicycle_proto::{IngestSummary, FILE_DESCRIPTOR_SET};
+use engine::Records;
+
+type Scan = fn() -> Result<Records<Vec<u8>>, Box<dyn Error>>;
+type Ingest = fn(Vec<Vec<u8>>) -> Result<(), Box<dyn Error>>;
+
+struct Model {
+    name: &'static str,
+    scan: Scan,
+    ingest: Ingest,
+}
+
+const MODELS: &[Model] = &[
+    // ##START_EXPORT_MODELS##
+    Model {
+        name: "Example",
+        scan: crate::scan_encoded_examples,
+        ingest: crate::ingest_encoded_examples,
+    },
+    // ##END_EXPORT_MODELS##
+];
+
+lazy_static! {
+    static ref DESCRIPTOR_POOL: DescriptorPool =
+        DescriptorPool::decode(FILE_DESCRIPTOR_SET).expect("invalid file descriptor set");
+}
+
+fn find_model(name: &str) -> Result<(&'static Model, MessageDescriptor), Box<dyn Error>> {
+    let model = MODELS
+        .iter()
+        .find(|model| model.name == name)
+        .ok_or_else(|| format!("no model named '{}'", name))?;
+
+    let descriptor = DESCRIPTOR_POOL
+        .all_messages()
+        .find(|message| message.name() == name && message.parent_message().is_none())
+        .ok_or_else(|| format!("no descriptor for model '{}'", name))?;
+
+    Ok((model, descriptor))
+}
+
+/// exports the records of `models`, or of every model when empty, from a
+/// single snapshot. each item is a JSON line of `{"model": "...", "record":
+/// {...}}` with the record in the canonical protobuf JSON mapping.
+pub fn export(models: &[String]) -> Result<Records<String>, Box<dyn Error>> {
+    let names: Vec<&str> = if models.is_empty() {
+        MODELS.iter().map(|model| model.name).collect()
+    } else {
+        models.iter().map(|name| name.as_str()).collect()
+    };
+
+    let mut found = vec![];
+
+    for name in names {
+        found.push(find_model(name)?);
+    }
+
+    // each scan holds on to the snapshot it was opened in
+    let scans = engine::with_new_snapshot(|| {
+        let mut scans = vec![];
+
+        for (model, descriptor) in found {
+            scans.push(((model.scan)()?, model.name, descriptor));
+        }
+
+        Ok(scans)
+    })?;
+
+    Ok(Box::new(scans.into_iter().flat_map(
+        |(records, name, descriptor)| {
+            records.map(move |record| {
+                let message = DynamicMessage::decode(descriptor.clone(), &record?[..])?;
+                let record = message.serialize_with_options(
+                    serde_json::value::Serializer,
+                    &SerializeOptions::new(),
+                )?;
+
+                Ok(json!({ "model": name, "record": record }).to_string())
+            })
+        },
+    )))
+}
+
+/// whether `model` has any records. lets `bicycle build` check a model is
+/// empty before a schema change drops it.
+pub fn has_records(model: &str) -> Result<bool, Box<dyn Error>> {
+    let (model, _) = find_model(model)?;
+    Ok((model.scan)()?.next().is_some())
+}
+
+fn decode_line(line: &str) -> Result<(&'static Model, Vec<u8>), Box<dyn Error>> {
+    let value: Value = serde_json::from_str(line)?;
+
+    let name = value["model"].as_str().ok_or("line is missing a 'model'")?;
+    let (model, descriptor) = find_model(name)?;
+
+    let record = value.get("record").ok_or("line is missing a 'record'")?;
+    let message = DynamicMessage::deserialize(descriptor, record)?;
+
+    Ok((model, message.encode_to_vec()))
+}
+
+/// imports JSON lines in the format produced by `export`. lines that can't be
+/// decoded are counted as failed, the rest are ingested per model.
+pub fn import(lines: Vec<String>) -> IngestSummary {
+    let mut batches: Vec<(&'static Model, Vec<Vec<u8>>)> = vec![];
+    let mut summary = IngestSummary::default();
+
+    for line in lines.iter().filter(|line| !line.trim().is_empty()) {
+        match decode_line(line) {
+            Ok((model, record)) => {
+                match batches
+                    .iter_mut()
+                    .find(|(batch_model, _)| batch_model.name == model.name)
+                {
+                    Some((_, records)) => records.push(record),
+                    None => batches.push((model, vec![record])),
+                }
+            }
+            Err(err) => {
+                log::error!("failed to decode import line: {}", err);
+                summary.failed += 1;
+            }
+        }
+    }
+
+    for (model, records) in batches {
+        let count = records.len() as u64;
+
+        match (model.ingest)(records) {
+            Ok(_) => summary.written += count,
+            Err(err) => {
+                log::error!("failed to import '{}' records: {}", model.name, err);
+                summary.failed += count;
+            }
+        }
+    }
+
+    summary
+}
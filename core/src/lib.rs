@@ -23,9 +23,331 @@ pub use tonic;
 pub mod models;
 pub use models::*;
 
+mod export;
+pub use export::{export, has_records, import};
+
+pub mod ext;
+
 pub use prost;
 pub use prost_types;
 
+use std::error::Error;
+use std::fs::{create_dir_all, read_dir, remove_dir_all, File};
+use std::io::Read;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use prost::Message;
+use prost_types::value::Kind;
+
+use bicycle_proto::{
+    index_query::Expression, BackupManifest, ChangeOp, ChangesQuery, SnapshotLease,
+};
+
+pub use engine::{Config as EngineConfig, Retention, Tuning as EngineTuning};
+
+const DEFAULT_CHANGES_LIMIT: usize = 1000;
+const MAX_CHANGES_LIMIT: usize = 10_000;
+
+const DEFAULT_SNAPSHOT_LEASE: Duration = Duration::from_secs(30);
+const MAX_SNAPSHOT_LEASE: Duration = Duration::from_secs(600);
+
+const BACKUP_MANIFEST: &str = "bicycle_backup.bin";
+const BACKUP_DESCRIPTOR: &str = "bicycle_descriptor.bin";
+
+static BACKUP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// whether a record with primary key `pk` falls within the `IndexQuery` scope.
+pub(crate) fn in_scope(expression: &Option<Expression>, pk: &str) -> bool {
+    match expression {
+        Some(Expression::Eq(val)) => pk == val,
+        Some(Expression::Gte(val)) => pk >= val.as_str(),
+        Some(Expression::Lte(val)) => pk <= val.as_str(),
+        Some(Expression::BeginsWith(val)) => pk.starts_with(val.as_str()),
+        None => true,
+    }
+}
+
+/// reads the change log, across all models, after `query.after_seq`.
+/// `model` on each change is the SHOUTY_SNAKE_CASE name of its message.
+pub fn get_changes(query: ChangesQuery) -> Result<bicycle_proto::Changes, Box<dyn Error>> {
+    let limit = match query.limit as usize {
+        0 => DEFAULT_CHANGES_LIMIT,
+        limit => limit.min(MAX_CHANGES_LIMIT),
+    };
+
+    let changes = engine::get_changes(query.after_seq, limit)?
+        .into_iter()
+        .map(|change| {
+            let (op, record) = match change.op {
+                engine::Op::Put(v) => (ChangeOp::Put, v),
+                engine::Op::Delete => (ChangeOp::Delete, vec![]),
+            };
+
+            bicycle_proto::Change {
+                seq: change.seq,
+                timestamp: change.timestamp,
+                op: op as i32,
+                model: change.model,
+                pk: change.pk,
+                record,
+            }
+        })
+        .collect();
+
+    Ok(bicycle_proto::Changes { changes })
+}
+
+/// sets how long the change log is kept for.
+pub fn set_change_retention(retention: Retention) {
+    engine::set_change_retention(retention)
+}
+
+/// sets where and how the engine opens the database, before it's first used.
+pub fn configure(config: EngineConfig) -> Result<(), Box<dyn Error>> {
+    engine::configure(config)
+}
+
+/// pins a consistent view of the database for `lease.lease_ms`, see
+/// `with_snapshot`.
+pub fn begin_snapshot(lease: SnapshotLease) -> Result<bicycle_proto::Snapshot, Box<dyn Error>> {
+    let lease = match lease.lease_ms {
+        0 => DEFAULT_SNAPSHOT_LEASE,
+        ms => Duration::from_millis(ms).min(MAX_SNAPSHOT_LEASE),
+    };
+
+    let id = engine::begin_snapshot(lease)?;
+
+    let expires_at = SystemTime::now()
+        .checked_add(lease)
+        .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+
+    Ok(bicycle_proto::Snapshot { id, expires_at })
+}
+
+/// releases a snapshot before its lease runs out.
+pub fn end_snapshot(snapshot: bicycle_proto::Snapshot) {
+    engine::end_snapshot(&snapshot.id)
+}
+
+/// runs `read` with every `get_*` and `stream_*` call it makes reading from
+/// the snapshot with `id`.
+pub fn with_snapshot<F, R>(id: &str, read: F) -> Result<R, Box<dyn Error>>
+where
+    F: FnOnce() -> Result<R, Box<dyn Error>>,
+{
+    engine::with_snapshot(id, read)
+}
+
+fn append_file<W: std::io::Write>(
+    archive: &mut tar::Builder<W>,
+    name: &str,
+    data: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+    );
+    header.set_cksum();
+
+    archive.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+/// takes a consistent backup while the database stays online, written to
+/// `archive` as a tar of the engine's files, the schema descriptor and a
+/// `BackupManifest`.
+pub fn backup(archive: &Path) -> Result<BackupManifest, Box<dyn Error>> {
+    let staging = std::env::temp_dir().join(format!(
+        "bicycle-backup-{}-{}",
+        std::process::id(),
+        BACKUP_COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    create_dir_all(&staging)?;
+
+    let res = (|| {
+        let seq = engine::backup(&staging)?;
+
+        let manifest = BackupManifest {
+            engine: engine::NAME.to_string(),
+            seq,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+        };
+
+        let mut builder = tar::Builder::new(File::create(archive)?);
+        builder.append_dir_all(".", &staging)?;
+        append_file(&mut builder, BACKUP_MANIFEST, &manifest.encode_to_vec())?;
+        append_file(
+            &mut builder,
+            BACKUP_DESCRIPTOR,
+            bicycle_proto::FILE_DESCRIPTOR_SET,
+        )?;
+        builder.into_inner()?.sync_all()?;
+
+        Ok(manifest)
+    })();
+
+    remove_dir_all(&staging)?;
+    res
+}
+
+/// reads the `BackupManifest` out of a backup `archive`.
+pub fn backup_manifest(archive: &Path) -> Result<BackupManifest, Box<dyn Error>> {
+    let mut archive = tar::Archive::new(File::open(archive)?);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+
+        if entry.path()?.as_ref() == Path::new(BACKUP_MANIFEST) {
+            let mut buf = vec![];
+            entry.read_to_end(&mut buf)?;
+
+            return Ok(BackupManifest::decode(&buf[..])?);
+        }
+    }
+
+    Err("archive is missing a backup manifest".into())
+}
+
+/// unpacks a backup `archive` into `dir`, which must be new or empty. a server
+/// started from `dir` picks up where the backup was taken.
+pub fn restore(archive: &Path, dir: &Path) -> Result<BackupManifest, Box<dyn Error>> {
+    let manifest = backup_manifest(archive)?;
+
+    if manifest.engine != engine::NAME {
+        return Err(format!(
+            "backup was taken from the '{}' engine, not '{}'",
+            manifest.engine,
+            engine::NAME
+        )
+        .into());
+    }
+
+    if dir.exists() && read_dir(dir)?.next().is_some() {
+        return Err(format!("'{}' is not empty", dir.display()).into());
+    }
+
+    create_dir_all(dir)?;
+    tar::Archive::new(File::open(archive)?).unpack(dir)?;
+
+    Ok(manifest)
+}
+
+/// migrations of this build in version order, compiled into the server by
+/// `bicycle build` so they don't depend on the directory it's started in.
+pub const MIGRATIONS: &[(u64, &[u8])] = &[
+    // ##START_MIGRATIONS##
+    // ##END_MIGRATIONS##
+];
+
+/// version of the schema this build was generated for, which is the highest
+/// numbered migration.
+pub const SCHEMA_VERSION: u64 = match MIGRATIONS.last() {
+    Some((version, _)) => *version,
+    None => 0,
+};
+
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+/// version of the migration in progress, so one cut short can tell it's
+/// been resumed.
+const MIGRATING_KEY: &str = "migrating";
+
+fn get_version_meta(key: &str) -> Result<u64, Box<dyn Error>> {
+    match engine::get_meta(key)? {
+        Some(v) => Ok(u64::from_le_bytes(
+            v.try_into()
+                .map_err(|_| format!("invalid '{}' meta", key))?,
+        )),
+        None => Ok(0),
+    }
+}
+
+/// runs the migrations newer than the database's schema version, in order,
+/// then records `SCHEMA_VERSION`. the version is recorded
+/// after each migration, so a crash resumes with the migration it interrupted.
+pub fn migrate() -> Result<(), Box<dyn Error>> {
+    let current = get_version_meta(SCHEMA_VERSION_KEY)?;
+
+    match current.cmp(&SCHEMA_VERSION) {
+        std::cmp::Ordering::Greater => {
+            return Err(format!(
+                "database is at schema version {} but this build is for version {}",
+                current, SCHEMA_VERSION
+            )
+            .into())
+        }
+        std::cmp::Ordering::Equal => {
+            log::info!("schema version {}", current);
+            return Ok(());
+        }
+        std::cmp::Ordering::Less => {}
+    }
+
+    let versions: Vec<&(u64, &[u8])> = MIGRATIONS
+        .iter()
+        .filter(|(version, _)| *version > current)
+        .collect();
+
+    let interrupted = get_version_meta(MIGRATING_KEY)?;
+    let wasm = biplane::wasmtime::Engine::default();
+
+    for (i, (version, bytes)) in versions.iter().enumerate() {
+        let resumed = *version == interrupted;
+
+        log::info!(
+            "{} migration {} ({}/{})",
+            if resumed { "resuming" } else { "running" },
+            version,
+            i + 1,
+            versions.len()
+        );
+        let now = Instant::now();
+
+        engine::put_meta(MIGRATING_KEY, &version.to_le_bytes())?;
+
+        let module = biplane::compile_module(bytes, &wasm)?;
+        let args = prost_types::Value {
+            kind: Some(Kind::StructValue(prost_types::Struct {
+                fields: [
+                    ("version", Kind::NumberValue(*version as f64)),
+                    ("resumed", Kind::BoolValue(resumed)),
+                ]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), prost_types::Value { kind: Some(v) }))
+                .collect(),
+            })),
+        };
+
+        biplane::invoke_module(&wasm, &module, &Some(args))
+            .map_err(|err| format!("migration {} failed: {}", version, err))?;
+
+        engine::put_meta(SCHEMA_VERSION_KEY, &version.to_le_bytes())?;
+        log::info!(
+            "migration {} done. [{}ms]",
+            version,
+            now.elapsed().as_millis()
+        );
+    }
+
+    engine::put_meta(SCHEMA_VERSION_KEY, &SCHEMA_VERSION.to_le_bytes())?;
+    engine::delete_meta(MIGRATING_KEY)?;
+    log::info!("schema version {}", SCHEMA_VERSION);
+
+    Ok(())
+}
+
 pub mod biplane {
     use parking_lot::Mutex;
     use std::error::Error;
@@ -236,10 +558,15 @@ pub mod biplane {
 
         linker.module(&mut store, "", module)?;
 
-        linker
-            .get_default(&mut store, "")?
-            .typed::<(), ()>(&store)?
-            .call(&mut store, ())?;
+        // every read in an invocation sees the same view of the database
+        engine::with_new_snapshot(|| {
+            linker
+                .get_default(&mut store, "")?
+                .typed::<(), ()>(&store)?
+                .call(&mut store, ())?;
+
+            Ok(())
+        })?;
 
         let out = out.lock();
         Ok(out.clone())
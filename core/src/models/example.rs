@@ -21,11 +21,12 @@ use std::error::Error;
 
 use prost::Message;
 
-use bicycle_proto::{index_query::Expression, IndexQuery};
+use bicycle_proto::{index_query::Expression, ChangeOp, IndexQuery, WatchQuery};
 
 use engine::{
     batch_put, delete_begins_with, delete_eq, delete_gte, delete_lte, get_begins_with, get_eq,
-    get_gte, get_lte, put,
+    get_gte, get_lte, ingest, put, stream_begins_with, stream_eq, stream_gte, stream_lte, watch,
+    Op, Records, Watcher,
 };
 
 const MODEL_NAME: &'static str = "EXAMPLE";
@@ -47,6 +48,23 @@ pub fn get_examples_by_pk(
     }
 }
 
+pub fn stream_examples_by_pk(
+    query: IndexQuery,
+) -> Result<Records<bicycle_proto::Example>, Box<dyn Error>> {
+    if let Some(expression) = query.expression {
+        match expression {
+            Expression::Eq(val) => stream_eq::<bicycle_proto::Example>(MODEL_NAME, &val),
+            Expression::Gte(val) => stream_gte::<bicycle_proto::Example>(MODEL_NAME, &val),
+            Expression::Lte(val) => stream_lte::<bicycle_proto::Example>(MODEL_NAME, &val),
+            Expression::BeginsWith(val) => {
+                stream_begins_with::<bicycle_proto::Example>(MODEL_NAME, &val)
+            }
+        }
+    } else {
+        Err("no expression provided".into())
+    }
+}
+
 pub fn delete_examples_by_pk(query: IndexQuery) -> Result<(), Box<dyn Error>> {
     if let Some(expression) = query.expression {
         match expression {
@@ -60,9 +78,23 @@ pub fn delete_examples_by_pk(query: IndexQuery) -> Result<(), Box<dyn Error>> {
     }
 }
 
+/// unix timestamp in seconds after which a record is no longer readable, set
+/// by a `uint64 expires_at` field on the model. `0` never expires.
+#[inline(always)]
+// ##START_EXPIRES_AT##
+fn expires_at(_example: &bicycle_proto::Example) -> Option<u64> {
+    None
+}
+// ##END_EXPIRES_AT##
+
 #[inline(always)]
 pub fn put_example(example: bicycle_proto::Example) -> Result<(), Box<dyn Error>> {
-    put(MODEL_NAME, example.pk.clone(), example.encode_to_vec())
+    put(
+        MODEL_NAME,
+        example.pk.clone(),
+        example.encode_to_vec(),
+        expires_at(&example),
+    )
 }
 
 #[inline]
@@ -70,8 +102,86 @@ pub fn batch_put_examples(examples: bicycle_proto::Examples) -> Result<(), Box<d
     let mut params = vec![];
 
     for example in examples.examples {
-        params.push((example.pk.clone(), example.encode_to_vec()));
+        params.push((
+            example.pk.clone(),
+            example.encode_to_vec(),
+            expires_at(&example),
+        ));
     }
 
     batch_put(MODEL_NAME, params)
 }
+
+#[inline]
+pub fn ingest_examples(examples: Vec<bicycle_proto::Example>) -> Result<(), Box<dyn Error>> {
+    let mut params = vec![];
+
+    for example in examples {
+        params.push((
+            example.pk.clone(),
+            example.encode_to_vec(),
+            expires_at(&example),
+        ));
+    }
+
+    ingest(MODEL_NAME, params)
+}
+
+/// every `Example` record, encoded, for exports.
+pub(crate) fn scan_encoded_examples() -> Result<Records<Vec<u8>>, Box<dyn Error>> {
+    let examples = stream_begins_with::<bicycle_proto::Example>(MODEL_NAME, "")?;
+    Ok(Box::new(examples.map(|example| {
+        example.map(|example| example.encode_to_vec())
+    })))
+}
+
+/// decodes and ingests encoded `Example` records, for imports.
+pub(crate) fn ingest_encoded_examples(encoded: Vec<Vec<u8>>) -> Result<(), Box<dyn Error>> {
+    let mut examples = vec![];
+
+    for v in encoded {
+        examples.push(bicycle_proto::Example::decode(&v[..])?);
+    }
+
+    ingest_examples(examples)
+}
+
+/// receives changes to `Example` records within a `WatchQuery` scope.
+pub struct ExampleWatcher {
+    changes: Watcher,
+    expression: Option<Expression>,
+}
+
+impl ExampleWatcher {
+    /// waits for the next change in scope.
+    pub async fn next(&mut self) -> Result<bicycle_proto::ExampleChange, Box<dyn Error>> {
+        loop {
+            let change = self.changes.recv().await?;
+
+            if !crate::in_scope(&self.expression, &change.pk) {
+                continue;
+            }
+
+            let (op, example) = match change.op {
+                Op::Put(v) => (ChangeOp::Put, Some(bicycle_proto::Example::decode(&v[..])?)),
+                Op::Delete => (ChangeOp::Delete, None),
+            };
+
+            return Ok(bicycle_proto::ExampleChange {
+                seq: change.seq,
+                op: op as i32,
+                pk: change.pk,
+                example,
+            });
+        }
+    }
+}
+
+pub fn watch_examples(query: WatchQuery) -> Result<ExampleWatcher, Box<dyn Error>> {
+    let changes = watch(MODEL_NAME, query.after_seq)?;
+
+    Ok(ExampleWatcher {
+        changes,
+        expression: query.query.and_then(|query| query.expression),
+    })
+}
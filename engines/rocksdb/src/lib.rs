@@ -20,26 +20,184 @@ along with this program.  If not, see <http://www.gnu.org/licenses/>.
 #[macro_use]
 extern crate lazy_static;
 
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
+use std::fs::remove_file;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
 use std::str::from_utf8;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
 
 use rocksdb::{
-    DBAccess, DBIteratorWithThreadMode, Direction, IteratorMode, Options, WriteBatch, DB,
+    checkpoint::Checkpoint, compaction_filter::Decision, DBAccess, DBIteratorWithThreadMode,
+    Direction, IteratorMode, Options, SstFileWriter, WriteBatch, DB,
 };
 
 use log::{error, info};
 
+use serde::Deserialize;
+
+/// name of the engine, recorded in backups.
+pub const NAME: &str = "rocksdb";
+
+const DB_PATH: &str = "__bicycle.engine.rocksdb__";
+
+/// batches at least this large are written to an SST file and ingested
+/// directly rather than going through the memtable.
+const SST_INGEST_THRESHOLD: usize = 10_000;
+
+static SST_INGEST_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+const CHANGE_LOG_PREFIX: &str = "__changes__#";
+const META_PREFIX: &str = "__meta__#";
+
+/// the change log is pruned each time this many changes have been written.
+const PRUNE_INTERVAL: u64 = 1000;
+
+/// number of changes a watcher can fall behind by before it's disconnected.
+const WATCH_BUFFER_SIZE: usize = 10_000;
+const WATCH_REPLAY_PAGE_SIZE: usize = 1000;
+
+/// how often snapshots past their lease are released.
+const SNAPSHOT_REAP_INTERVAL: Duration = Duration::from_secs(1);
+
+static SNAPSHOT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// records with an expiry have it appended as a trailing fixed64 field using
+/// the largest valid field number, which decoders skip as an unknown field.
+const EXPIRY_TAG: [u8; 5] = [0xF9, 0xFF, 0xFF, 0xFF, 0x0F];
+
+/// RocksDB specific settings, the `[engine]` table of a server's
+/// `bicycle.toml`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tuning {
+    /// files RocksDB keeps open at once, unlimited when unset.
+    pub max_open_files: Option<i32>,
+    /// bytes written to a memtable before it's flushed to disk.
+    pub write_buffer_size: Option<usize>,
+    /// background threads for flushes and compactions.
+    pub parallelism: Option<i32>,
+}
+
+/// where and how the database is opened.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// directory the database is kept in, the working directory when empty.
+    pub data_dir: PathBuf,
+    pub tuning: Tuning,
+    /// opens an existing database for reading only, without taking it from
+    /// a server that has it open. a missing one is created as usual.
+    pub read_only: bool,
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/// sets where and how the database is opened, which has to happen before it's
+/// first used.
+pub fn configure(config: Config) -> Result<(), Box<dyn Error>> {
+    CONFIG
+        .set(config)
+        .map_err(|_| "the engine was already configured or opened".into())
+}
+
 lazy_static! {
     static ref ROCKSDB: DB = {
+        let config = config();
+
+        if !config.data_dir.as_os_str().is_empty() {
+            std::fs::create_dir_all(&config.data_dir).expect("unable to create data directory");
+        }
+
         let mut opts = Options::default();
         opts.create_if_missing(true);
 
-        DB::open(&opts, "__bicycle.engine.rocksdb__").expect("unable to open RocksDB")
+        if let Some(max_open_files) = config.tuning.max_open_files {
+            opts.set_max_open_files(max_open_files);
+        }
+        if let Some(write_buffer_size) = config.tuning.write_buffer_size {
+            opts.set_write_buffer_size(write_buffer_size);
+        }
+        if let Some(parallelism) = config.tuning.parallelism {
+            opts.increase_parallelism(parallelism);
+        }
+
+        // expired records are dropped as their SST files are compacted
+        opts.set_compaction_filter("bicycle_expiry", |_level, key: &[u8], value: &[u8]| {
+            if !key.starts_with(b"__") && is_expired(value) {
+                Decision::Remove
+            } else {
+                Decision::Keep
+            }
+        });
+
+        let path = config.data_dir.join(DB_PATH);
+
+        // read-only opens skip the lock a running server holds
+        if config.read_only && path.exists() {
+            DB::open_for_read_only(&opts, path, false).expect("unable to open RocksDB")
+        } else {
+            DB::open(&opts, path).expect("unable to open RocksDB")
+        }
     };
+    static ref CHANGES: Mutex<Changes> = Mutex::new(Changes::load());
+    static ref SNAPSHOTS: Mutex<HashMap<String, Arc<Snapshot>>> = {
+        std::thread::spawn(reap_snapshots);
+        Mutex::new(HashMap::new())
+    };
+}
+
+thread_local! {
+    static ACTIVE_SNAPSHOT: RefCell<Option<Arc<Snapshot>>> = const { RefCell::new(None) };
 }
 
 // HELPERS
 
+fn with_expiry(mut v: Vec<u8>, expires_at: Option<u64>) -> Vec<u8> {
+    if let Some(expires_at) = expires_at {
+        v.extend_from_slice(&EXPIRY_TAG);
+        v.extend_from_slice(&expires_at.to_le_bytes());
+    }
+
+    v
+}
+
+/// splits the expiry trailer off of a stored value, if it has one.
+fn split_expiry(v: &[u8]) -> (&[u8], Option<u64>) {
+    if v.len() < EXPIRY_TAG.len() + 8 {
+        return (v, None);
+    }
+
+    let (rest, expires_at) = v.split_at(v.len() - 8);
+
+    match rest.strip_suffix(&EXPIRY_TAG) {
+        Some(rest) => (
+            rest,
+            Some(u64::from_le_bytes(
+                expires_at.try_into().unwrap_or_default(),
+            )),
+        ),
+        None => (v, None),
+    }
+}
+
+fn is_expired(v: &[u8]) -> bool {
+    match split_expiry(v) {
+        (_, Some(expires_at)) => expires_at <= now_millis() / 1000,
+        _ => false,
+    }
+}
+
 fn handle_get_itr<'a, D, T>(
     model: &'static str,
     itr: &mut DBIteratorWithThreadMode<'a, D>,
@@ -54,6 +212,10 @@ where
     while let Some(Ok((k, v))) = itr.next() {
         if let Ok(key) = from_utf8(&*k) {
             if key.starts_with(model_str) {
+                if is_expired(&v) {
+                    continue;
+                }
+
                 if let Ok(item) = prost::Message::decode(&*v) {
                     items.push(item);
                 } else {
@@ -78,53 +240,554 @@ where
 {
     let model_str = &format!("{}#", model);
     let mut batch = WriteBatch::default();
+    let mut ops = vec![];
 
     while let Some(Ok((k, ..))) = itr.next() {
         if let Ok(key) = from_utf8(&*k) {
             if key.starts_with(model_str) {
                 batch.delete(key);
+                ops.push((key[model_str.len()..].to_string(), Op::Delete));
             } else {
                 break;
             }
         }
     }
 
+    write_logged(model, batch, ops)
+}
+
+/// writes `batch` along with change log entries for `ops`.
+fn write_logged(
+    model: &'static str,
+    mut batch: WriteBatch,
+    ops: Vec<(String, Op)>,
+) -> Result<(), Box<dyn Error>> {
+    let mut changes = CHANGES.lock();
+    let logged = changes.log(model, ops);
+
+    for change in logged.iter() {
+        batch.put(change_key(change.seq), change.encode_to_vec());
+    }
+
     ROCKSDB.write(batch)?;
+    changes.publish(logged);
     Ok(())
 }
 
+// CHANGES
+
+#[derive(Clone, Debug)]
+pub enum Op {
+    Put(Vec<u8>),
+    Delete,
+}
+
+/// a committed write to a single record.
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub seq: u64,
+    /// milliseconds since the unix epoch.
+    pub timestamp: u64,
+    pub model: String,
+    pub pk: String,
+    pub op: Op,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct LogEntry {
+    #[prost(uint64, tag = "1")]
+    timestamp: u64,
+    #[prost(string, tag = "2")]
+    model: String,
+    #[prost(string, tag = "3")]
+    pk: String,
+    #[prost(bytes = "vec", optional, tag = "4")]
+    record: Option<Vec<u8>>,
+}
+
+impl Change {
+    fn encode_to_vec(&self) -> Vec<u8> {
+        let record = match &self.op {
+            Op::Put(v) => Some(v.clone()),
+            Op::Delete => None,
+        };
+
+        prost::Message::encode_to_vec(&LogEntry {
+            timestamp: self.timestamp,
+            model: self.model.clone(),
+            pk: self.pk.clone(),
+            record,
+        })
+    }
+
+    fn decode(seq: u64, v: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let entry: LogEntry = prost::Message::decode(v)?;
+
+        Ok(Change {
+            seq,
+            timestamp: entry.timestamp,
+            model: entry.model,
+            pk: entry.pk,
+            op: match entry.record {
+                Some(v) => Op::Put(v),
+                None => Op::Delete,
+            },
+        })
+    }
+}
+
+/// how long the change log is kept for, changes outside either limit are
+/// pruned as new ones are written.
+#[derive(Clone, Debug)]
+pub struct Retention {
+    pub max_changes: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            max_changes: Some(1_000_000),
+            max_age: None,
+        }
+    }
+}
+
+fn change_key(seq: u64) -> String {
+    format!("{}{:020}", CHANGE_LOG_PREFIX, seq)
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+struct Changes {
+    seq: u64,
+    retention: Retention,
+    watchers: Vec<(&'static str, Sender<Change>)>,
+}
+
+impl Changes {
+    fn load() -> Self {
+        let mut itr = ROCKSDB.iterator(IteratorMode::From(
+            format!("{}~", CHANGE_LOG_PREFIX).as_bytes(),
+            Direction::Reverse,
+        ));
+
+        let mut seq = 0;
+
+        if let Some(Ok((k, ..))) = itr.next() {
+            if let Ok(key) = from_utf8(&k) {
+                if let Some(last) = key.strip_prefix(CHANGE_LOG_PREFIX) {
+                    seq = last.parse().expect("invalid change log key");
+                }
+            }
+        }
+
+        Changes {
+            seq,
+            retention: Retention::default(),
+            watchers: vec![],
+        }
+    }
+
+    /// assigns sequence numbers to `ops`, to be written with the records.
+    fn log(&mut self, model: &'static str, ops: Vec<(String, Op)>) -> Vec<Change> {
+        let timestamp = now_millis();
+
+        ops.into_iter()
+            .map(|(pk, op)| {
+                self.seq += 1;
+
+                Change {
+                    seq: self.seq,
+                    timestamp,
+                    model: model.to_string(),
+                    pk,
+                    op,
+                }
+            })
+            .collect()
+    }
+
+    fn publish(&mut self, logged: Vec<Change>) {
+        let before = self.seq - logged.len() as u64;
+
+        for change in logged {
+            self.watchers.retain(|(watched, watcher)| {
+                *watched != change.model || watcher.try_send(change.clone()).is_ok()
+            });
+        }
+
+        if before / PRUNE_INTERVAL != self.seq / PRUNE_INTERVAL {
+            if let Err(err) = self.prune() {
+                error!("failed to prune change log: {}", err);
+            }
+        }
+    }
+
+    fn prune(&self) -> Result<(), Box<dyn Error>> {
+        let mut first_kept = 0;
+
+        if let Some(max_changes) = self.retention.max_changes {
+            first_kept = self.seq.saturating_sub(max_changes) + 1;
+        }
+
+        if let Some(max_age) = self.retention.max_age {
+            let cutoff = now_millis().saturating_sub(max_age.as_millis() as u64);
+
+            let itr = ROCKSDB.iterator(IteratorMode::From(
+                change_key(first_kept).as_bytes(),
+                Direction::Forward,
+            ));
+
+            for item in itr {
+                let (k, v) = item?;
+
+                let seq = match from_utf8(&k)?.strip_prefix(CHANGE_LOG_PREFIX) {
+                    Some(seq) => seq.parse::<u64>()?,
+                    None => break,
+                };
+
+                if Change::decode(seq, &v)?.timestamp >= cutoff {
+                    break;
+                }
+
+                first_kept = seq + 1;
+            }
+        }
+
+        if first_kept > 0 {
+            let mut batch = WriteBatch::default();
+            batch.delete_range(change_key(0), change_key(first_kept));
+            ROCKSDB.write(batch)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// sets how long the change log is kept for.
+pub fn set_change_retention(retention: Retention) {
+    CHANGES.lock().retention = retention;
+}
+
+/// reads up to `limit` changes, across all models, after `after`.
+pub fn get_changes(after: u64, limit: usize) -> Result<Vec<Change>, Box<dyn Error>> {
+    let itr = ROCKSDB.iterator(IteratorMode::From(
+        change_key(after + 1).as_bytes(),
+        Direction::Forward,
+    ));
+
+    let mut changes = vec![];
+
+    for item in itr.take(limit) {
+        let (k, v) = item?;
+
+        match from_utf8(&k)?.strip_prefix(CHANGE_LOG_PREFIX) {
+            Some(seq) => changes.push(Change::decode(seq.parse()?, &v)?),
+            None => break,
+        }
+    }
+
+    info!("get_changes");
+    Ok(changes)
+}
+
+fn oldest_change() -> Result<Option<u64>, Box<dyn Error>> {
+    Ok(get_changes(0, 1)?.first().map(|change| change.seq))
+}
+
+/// receives the changes made to a model, replaying the change log before
+/// switching over to live changes.
+pub struct Watcher {
+    model: &'static str,
+    cursor: u64,
+    until: u64,
+    backlog: std::vec::IntoIter<Change>,
+    live: Receiver<Change>,
+}
+
+impl Watcher {
+    /// waits for the next change, without holding a thread while it waits.
+    pub async fn recv(&mut self) -> Result<Change, Box<dyn Error>> {
+        loop {
+            if let Some(change) = self.backlog.next() {
+                return Ok(change);
+            }
+
+            if self.cursor >= self.until {
+                break;
+            }
+
+            let page = get_changes(self.cursor, WATCH_REPLAY_PAGE_SIZE)?;
+
+            self.cursor = match page.last() {
+                Some(last) => last.seq,
+                None => self.until,
+            };
+
+            self.backlog = page
+                .into_iter()
+                .filter(|change| change.model == self.model && change.seq <= self.until)
+                .collect::<Vec<Change>>()
+                .into_iter();
+        }
+
+        // publishing drops the sender once the buffer is full
+        match self.live.recv().await {
+            Some(change) => Ok(change),
+            None => Err("watcher fell behind, resume from the last received seq".into()),
+        }
+    }
+}
+
+/// watches for changes to `model`, replaying those after `after` first.
+pub fn watch(model: &'static str, after: Option<u64>) -> Result<Watcher, Box<dyn Error>> {
+    let mut changes = CHANGES.lock();
+    let (tx, rx) = channel(WATCH_BUFFER_SIZE);
+
+    let cursor = after.unwrap_or(changes.seq);
+
+    if cursor > changes.seq {
+        return Err(format!("seq {} is ahead of latest seq {}", cursor, changes.seq).into());
+    }
+
+    if cursor < changes.seq {
+        match oldest_change()? {
+            Some(oldest) if cursor + 1 >= oldest => {}
+            _ => {
+                return Err(format!("changes after seq {} are no longer available", cursor).into())
+            }
+        }
+    }
+
+    changes.watchers.push((model, tx));
+    info!("watch {}", model);
+
+    Ok(Watcher {
+        model,
+        cursor,
+        until: changes.seq,
+        backlog: vec![].into_iter(),
+        live: rx,
+    })
+}
+
+// SNAPSHOTS
+
+/// a consistent view of the database that reads can be pinned to.
+struct Snapshot {
+    inner: rocksdb::Snapshot<'static>,
+    expires_at: Instant,
+}
+
+fn active_snapshot() -> Option<Arc<Snapshot>> {
+    ACTIVE_SNAPSHOT.with(|active| active.borrow().clone())
+}
+
+/// iterates `snapshot` when there is one, the live database otherwise. the
+/// snapshot must outlive the iterator.
+fn read_iterator(
+    snapshot: &Option<Arc<Snapshot>>,
+    mode: IteratorMode,
+) -> DBIteratorWithThreadMode<'static, DB> {
+    match snapshot {
+        Some(snapshot) => snapshot.inner.iterator(mode),
+        None => ROCKSDB.iterator(mode),
+    }
+}
+
+fn reap_snapshots() {
+    loop {
+        std::thread::sleep(SNAPSHOT_REAP_INTERVAL);
+
+        let now = Instant::now();
+        SNAPSHOTS
+            .lock()
+            .retain(|_, snapshot| snapshot.expires_at > now);
+    }
+}
+
+/// pins a snapshot of the database until `end_snapshot` is called or `lease`
+/// runs out, returning its id.
+pub fn begin_snapshot(lease: Duration) -> Result<String, Box<dyn Error>> {
+    let id = format!(
+        "{:x}-{:x}",
+        now_millis(),
+        SNAPSHOT_COUNT.fetch_add(1, Ordering::Relaxed)
+    );
+
+    let snapshot = Snapshot {
+        inner: ROCKSDB.snapshot(),
+        expires_at: Instant::now() + lease,
+    };
+
+    SNAPSHOTS.lock().insert(id.clone(), Arc::new(snapshot));
+
+    info!("begin_snapshot {}", id);
+    Ok(id)
+}
+
+/// releases a snapshot, reads already in flight against it can finish.
+pub fn end_snapshot(id: &str) {
+    SNAPSHOTS.lock().remove(id);
+    info!("end_snapshot {}", id);
+}
+
+/// restores the previously active snapshot when dropped, including when a
+/// read panics.
+struct ActiveSnapshotGuard(Option<Arc<Snapshot>>);
+
+impl Drop for ActiveSnapshotGuard {
+    fn drop(&mut self) {
+        let prev = self.0.take();
+        ACTIVE_SNAPSHOT.with(|active| active.replace(prev));
+    }
+}
+
+fn with_active_snapshot<F, R>(snapshot: Arc<Snapshot>, read: F) -> Result<R, Box<dyn Error>>
+where
+    F: FnOnce() -> Result<R, Box<dyn Error>>,
+{
+    let _guard = ActiveSnapshotGuard(ACTIVE_SNAPSHOT.with(|active| active.replace(Some(snapshot))));
+
+    read()
+}
+
+/// runs `read` with every read on this thread pinned to snapshot `id`.
+pub fn with_snapshot<F, R>(id: &str, read: F) -> Result<R, Box<dyn Error>>
+where
+    F: FnOnce() -> Result<R, Box<dyn Error>>,
+{
+    let snapshot = SNAPSHOTS
+        .lock()
+        .get(id)
+        .filter(|snapshot| snapshot.expires_at > Instant::now())
+        .cloned()
+        .ok_or("snapshot not found or its lease has expired")?;
+
+    with_active_snapshot(snapshot, read)
+}
+
+/// runs `read` with every read on this thread pinned to a new snapshot,
+/// released when `read` returns.
+pub fn with_new_snapshot<F, R>(read: F) -> Result<R, Box<dyn Error>>
+where
+    F: FnOnce() -> Result<R, Box<dyn Error>>,
+{
+    // never registered, so the lease isn't checked
+    let snapshot = Snapshot {
+        inner: ROCKSDB.snapshot(),
+        expires_at: Instant::now(),
+    };
+
+    with_active_snapshot(Arc::new(snapshot), read)
+}
+
 // PUT
 
-pub fn put(model: &'static str, k: String, v: Vec<u8>) -> Result<(), Box<dyn Error>> {
-    ROCKSDB.put(format!("{}#{}", model, k).as_bytes(), v)?;
+pub fn put(
+    model: &'static str,
+    k: String,
+    v: Vec<u8>,
+    expires_at: Option<u64>,
+) -> Result<(), Box<dyn Error>> {
+    let v = with_expiry(v, expires_at);
+    let mut batch = WriteBatch::default();
+    batch.put(format!("{}#{}", model, k).as_bytes(), &v);
+
+    write_logged(model, batch, vec![(k, Op::Put(v))])?;
     info!("put {}", model);
     Ok(())
 }
 
 pub fn batch_put(
     model: &'static str,
-    params: Vec<(String, Vec<u8>)>,
+    params: Vec<(String, Vec<u8>, Option<u64>)>,
 ) -> Result<(), Box<dyn Error>> {
     let mut batch = WriteBatch::default();
+    let mut ops = vec![];
 
-    for (k, v) in params {
-        batch.put(format!("{}#{}", model, k).as_bytes(), v);
+    for (k, v, expires_at) in params {
+        let v = with_expiry(v, expires_at);
+        batch.put(format!("{}#{}", model, k).as_bytes(), &v);
+        ops.push((k, Op::Put(v)));
     }
 
-    ROCKSDB.write(batch)?;
+    write_logged(model, batch, ops)?;
     info!("batch_put {}", model);
     Ok(())
 }
 
+pub fn ingest(
+    model: &'static str,
+    params: Vec<(String, Vec<u8>, Option<u64>)>,
+) -> Result<(), Box<dyn Error>> {
+    if params.len() < SST_INGEST_THRESHOLD {
+        return batch_put(model, params);
+    }
+
+    // SST files must be written in key order, last write for a key wins
+    let mut records = BTreeMap::new();
+
+    for (k, v, expires_at) in params {
+        records.insert(k, with_expiry(v, expires_at));
+    }
+
+    let path = std::env::temp_dir().join(format!(
+        "bicycle-ingest-{}-{}.sst",
+        std::process::id(),
+        SST_INGEST_COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let opts = Options::default();
+    let mut writer = SstFileWriter::create(&opts);
+    writer.open(&path)?;
+
+    let mut ops = vec![];
+
+    for (k, v) in records {
+        writer.put(format!("{}#{}", model, k).as_bytes(), &v)?;
+        ops.push((k, Op::Put(v)));
+    }
+
+    let mut changes = CHANGES.lock();
+    let logged = changes.log(model, ops);
+
+    // model keys are SHOUTY_SNAKE_CASE so always sort before the change log
+    for change in logged.iter() {
+        writer.put(change_key(change.seq), change.encode_to_vec())?;
+    }
+
+    writer.finish()?;
+
+    let res = ROCKSDB.ingest_external_file(vec![&path]);
+    remove_file(&path)?;
+    res?;
+    changes.publish(logged);
+
+    info!("ingest {}", model);
+    Ok(())
+}
+
 // GET
 
 pub fn get_eq<T>(model: &'static str, val: &str) -> Result<Vec<T>, Box<dyn Error>>
 where
     T: prost::Message + Default,
 {
-    let res = ROCKSDB.get(format!("{}#{}", model, val).as_bytes())?;
+    let key = format!("{}#{}", model, val);
+
+    let res = match active_snapshot() {
+        Some(snapshot) => snapshot.inner.get(key.as_bytes())?,
+        None => ROCKSDB.get(key.as_bytes())?,
+    };
 
-    if let Some(res) = res {
+    if let Some(res) = res.filter(|res| !is_expired(res)) {
         let decoded = prost::Message::decode(&res[..])?;
         info!("get_eq {}", model);
         Ok(vec![decoded])
@@ -138,10 +801,11 @@ pub fn get_gte<T>(model: &'static str, val: &str) -> Result<Vec<T>, Box<dyn Erro
 where
     T: prost::Message + Default,
 {
-    let mut itr = ROCKSDB.iterator(IteratorMode::From(
-        format!("{}#{}", model, val).as_bytes(),
-        Direction::Forward,
-    ));
+    let snapshot = active_snapshot();
+    let mut itr = read_iterator(
+        &snapshot,
+        IteratorMode::From(format!("{}#{}", model, val).as_bytes(), Direction::Forward),
+    );
 
     let res = handle_get_itr(model, &mut itr);
     info!("get_gte {}", model);
@@ -153,10 +817,11 @@ pub fn get_lte<T>(model: &'static str, val: &str) -> Result<Vec<T>, Box<dyn Erro
 where
     T: prost::Message + Default,
 {
-    let mut itr = ROCKSDB.iterator(IteratorMode::From(
-        format!("{}#{}", model, val).as_bytes(),
-        Direction::Reverse,
-    ));
+    let snapshot = active_snapshot();
+    let mut itr = read_iterator(
+        &snapshot,
+        IteratorMode::From(format!("{}#{}", model, val).as_bytes(), Direction::Reverse),
+    );
 
     let res = handle_get_itr(model, &mut itr);
     info!("get_lte {}", model);
@@ -170,13 +835,21 @@ where
 {
     let val = format!("{}#{}", model, val);
 
-    let mut itr = ROCKSDB.iterator(IteratorMode::From(val.as_bytes(), Direction::Forward));
+    let snapshot = active_snapshot();
+    let mut itr = read_iterator(
+        &snapshot,
+        IteratorMode::From(val.as_bytes(), Direction::Forward),
+    );
 
     let mut items = vec![];
 
     while let Some(Ok((k, v))) = itr.next() {
         if let Ok(key) = from_utf8(&*k) {
             if key.starts_with(&val) {
+                if is_expired(&v) {
+                    continue;
+                }
+
                 if let Ok(item) = prost::Message::decode(&*v) {
                     items.push(item);
                 } else {
@@ -194,10 +867,183 @@ where
     Ok(items)
 }
 
+// STREAM
+
+/// lazily decoded records, read from the engine as the iterator is advanced.
+pub type Records<T> = Box<dyn Iterator<Item = Result<T, Box<dyn Error>>>>;
+
+struct PrefixRecords<T> {
+    prefix: String,
+    itr: DBIteratorWithThreadMode<'static, DB>,
+    // declared after `itr` so the snapshot it reads from outlives it
+    _snapshot: Option<Arc<Snapshot>>,
+    _item: PhantomData<T>,
+}
+
+impl<T> Iterator for PrefixRecords<T>
+where
+    T: prost::Message + Default,
+{
+    type Item = Result<T, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.itr.next()? {
+                Ok((k, v)) => {
+                    if !k.starts_with(self.prefix.as_bytes()) {
+                        return None;
+                    }
+
+                    if is_expired(&v) {
+                        continue;
+                    }
+
+                    return match prost::Message::decode(&*v) {
+                        Ok(item) => Some(Ok(item)),
+                        Err(err) => {
+                            error!("failed to decode record");
+                            Some(Err(err.into()))
+                        }
+                    };
+                }
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+    }
+}
+
+fn prefix_records<T>(prefix: String, from: String, direction: Direction) -> Records<T>
+where
+    T: prost::Message + Default + 'static,
+{
+    let snapshot = active_snapshot();
+    let itr = read_iterator(&snapshot, IteratorMode::From(from.as_bytes(), direction));
+
+    Box::new(PrefixRecords {
+        prefix,
+        itr,
+        _snapshot: snapshot,
+        _item: PhantomData,
+    })
+}
+
+/// a record as it's stored, without its model prefix: `(pk, encoded, expires_at)`.
+pub type RawRecord = (String, Vec<u8>, Option<u64>);
+
+struct ScanRecords {
+    prefix: String,
+    itr: DBIteratorWithThreadMode<'static, DB>,
+    // declared after `itr` so the snapshot it reads from outlives it
+    _snapshot: Option<Arc<Snapshot>>,
+}
+
+impl Iterator for ScanRecords {
+    type Item = Result<RawRecord, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.itr.next()? {
+                Ok((k, v)) => {
+                    if !k.starts_with(self.prefix.as_bytes()) {
+                        return None;
+                    }
+
+                    if is_expired(&v) {
+                        continue;
+                    }
+
+                    let pk = match from_utf8(&k[self.prefix.len()..]) {
+                        Ok(pk) => pk.to_string(),
+                        Err(err) => return Some(Err(err.into())),
+                    };
+                    let (v, expires_at) = split_expiry(&v);
+
+                    return Some(Ok((pk, v.to_vec(), expires_at)));
+                }
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+    }
+}
+
+/// every live record of `model` in primary key order, still encoded. used to
+/// move records between engines, which `ingest` takes back in the same shape.
+pub fn scan(model: &'static str) -> Result<Records<RawRecord>, Box<dyn Error>> {
+    let prefix = format!("{}#", model);
+    let snapshot = active_snapshot();
+    let itr = read_iterator(
+        &snapshot,
+        IteratorMode::From(prefix.as_bytes(), Direction::Forward),
+    );
+
+    info!("scan {}", model);
+    Ok(Box::new(ScanRecords {
+        prefix,
+        itr,
+        _snapshot: snapshot,
+    }))
+}
+
+pub fn stream_eq<T>(model: &'static str, val: &str) -> Result<Records<T>, Box<dyn Error>>
+where
+    T: prost::Message + Default + 'static,
+{
+    let res = get_eq::<T>(model, val)?;
+    info!("stream_eq {}", model);
+    Ok(Box::new(res.into_iter().map(Ok)))
+}
+
+pub fn stream_gte<T>(model: &'static str, val: &str) -> Result<Records<T>, Box<dyn Error>>
+where
+    T: prost::Message + Default + 'static,
+{
+    let res = prefix_records(
+        format!("{}#", model),
+        format!("{}#{}", model, val),
+        Direction::Forward,
+    );
+    info!("stream_gte {}", model);
+    Ok(res)
+}
+
+pub fn stream_lte<T>(model: &'static str, val: &str) -> Result<Records<T>, Box<dyn Error>>
+where
+    T: prost::Message + Default + 'static,
+{
+    let res = prefix_records(
+        format!("{}#", model),
+        format!("{}#{}", model, val),
+        Direction::Reverse,
+    );
+    info!("stream_lte {}", model);
+    Ok(res)
+}
+
+pub fn stream_begins_with<T>(model: &'static str, val: &str) -> Result<Records<T>, Box<dyn Error>>
+where
+    T: prost::Message + Default + 'static,
+{
+    let val = format!("{}#{}", model, val);
+
+    let res = prefix_records(val.clone(), val, Direction::Forward);
+    info!("stream_begins_with {}", model);
+    Ok(res)
+}
+
 // DELETE
 
 pub fn delete_eq(model: &'static str, val: &str) -> Result<(), Box<dyn Error>> {
-    ROCKSDB.delete(format!("{}#{}", model, val).as_bytes())?;
+    let key = format!("{}#{}", model, val);
+    let mut batch = WriteBatch::default();
+    let mut ops = vec![];
+
+    // like the other deletes, only a record that's there is logged
+    if ROCKSDB.get_pinned(key.as_bytes())?.is_some() {
+        batch.delete(key.as_bytes());
+        ops.push((val.to_string(), Op::Delete));
+    }
+
+    write_logged(model, batch, ops)?;
     info!("delete_eq {}", model);
     Ok(())
 }
@@ -229,19 +1075,330 @@ pub fn delete_begins_with(model: &'static str, val: &str) -> Result<(), Box<dyn
 
     let mut itr = ROCKSDB.iterator(IteratorMode::From(val.as_bytes(), Direction::Forward));
 
+    let model_str = format!("{}#", model);
     let mut batch = WriteBatch::default();
+    let mut ops = vec![];
 
     while let Some(Ok((k, ..))) = itr.next() {
         if let Ok(key) = from_utf8(&*k) {
             if key.starts_with(&val) {
-                batch.delete(key)
+                batch.delete(key);
+                ops.push((key[model_str.len()..].to_string(), Op::Delete));
             } else {
                 break;
             }
         }
     }
 
-    ROCKSDB.write(batch)?;
+    write_logged(model, batch, ops)?;
     info!("delete_begins_with {}", model);
     Ok(())
 }
+
+// META
+
+/// reads a value kept alongside the records, such as the schema version.
+/// meta isn't in the change log or visible to snapshots.
+pub fn get_meta(key: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    Ok(ROCKSDB.get(format!("{}{}", META_PREFIX, key))?)
+}
+
+pub fn put_meta(key: &str, v: &[u8]) -> Result<(), Box<dyn Error>> {
+    ROCKSDB.put(format!("{}{}", META_PREFIX, key), v)?;
+    info!("put_meta {}", key);
+    Ok(())
+}
+
+pub fn delete_meta(key: &str) -> Result<(), Box<dyn Error>> {
+    ROCKSDB.delete(format!("{}{}", META_PREFIX, key))?;
+    info!("delete_meta {}", key);
+    Ok(())
+}
+
+/// every meta key and value.
+pub fn meta() -> Result<HashMap<String, Vec<u8>>, Box<dyn Error>> {
+    let itr = ROCKSDB.iterator(IteratorMode::From(
+        META_PREFIX.as_bytes(),
+        Direction::Forward,
+    ));
+    let mut meta = HashMap::new();
+
+    for item in itr {
+        let (k, v) = item?;
+
+        if !k.starts_with(META_PREFIX.as_bytes()) {
+            break;
+        }
+
+        meta.insert(from_utf8(&k[META_PREFIX.len()..])?.to_string(), v.to_vec());
+    }
+
+    Ok(meta)
+}
+
+// BACKUP
+
+/// copies a consistent snapshot of the database into `dir`, laid out the same
+/// as a server's working directory, as a RocksDB checkpoint. returns the seq
+/// of the latest change included in the copy.
+pub fn backup(dir: &Path) -> Result<u64, Box<dyn Error>> {
+    // every write takes the change log lock, so holding it pins `seq` to the
+    // checkpoint. checkpoints hard link SST files so this is brief.
+    let changes = CHANGES.lock();
+    Checkpoint::new(&ROCKSDB)?.create_checkpoint(dir.join(DB_PATH))?;
+
+    info!("backup {}", changes.seq);
+    Ok(changes.seq)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Once;
+
+    use parking_lot::MutexGuard;
+    use prost::Message;
+
+    use super::*;
+
+    static INIT: Once = Once::new();
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    /// the engine is configured once per process, so tests share a database
+    /// and each keeps to models of its own. they run one at a time since the
+    /// change log and its retention are shared too.
+    fn init() -> MutexGuard<'static, ()> {
+        INIT.call_once(|| {
+            let data_dir =
+                std::env::temp_dir().join(format!("bicycle_rocksdb_{}", std::process::id()));
+
+            if data_dir.exists() {
+                std::fs::remove_dir_all(&data_dir).unwrap();
+            }
+
+            configure(Config {
+                data_dir,
+                tuning: Tuning::default(),
+                read_only: false,
+            })
+            .unwrap();
+        });
+
+        SERIAL.lock()
+    }
+
+    fn encode(name: &str) -> Vec<u8> {
+        name.to_string().encode_to_vec()
+    }
+
+    fn latest_seq() -> u64 {
+        CHANGES.lock().seq
+    }
+
+    fn summary(changes: &[Change]) -> Vec<(String, bool)> {
+        changes
+            .iter()
+            .map(|change| (change.pk.clone(), matches!(change.op, Op::Put(_))))
+            .collect()
+    }
+
+    #[test]
+    fn logs_changes_after_seq() {
+        let _serial = init();
+        let after = latest_seq();
+
+        put("LOGGED_DOG", "1".to_string(), encode("Rex"), None).unwrap();
+        put("LOGGED_DOG", "2".to_string(), encode("Max"), None).unwrap();
+        delete_eq("LOGGED_DOG", "1").unwrap();
+        delete_eq("LOGGED_DOG", "3").unwrap();
+
+        let changes = get_changes(after, 10).unwrap();
+
+        assert_eq!(
+            summary(&changes),
+            vec![
+                ("1".to_string(), true),
+                ("2".to_string(), true),
+                ("1".to_string(), false),
+            ]
+        );
+        assert_eq!(changes.last().unwrap().seq, latest_seq());
+    }
+
+    #[test]
+    fn watch_resumes_after_seq() {
+        let _serial = init();
+
+        put("WATCHED_DOG", "1".to_string(), encode("Rex"), None).unwrap();
+        let after = latest_seq();
+
+        put("WATCHED_CAT", "1".to_string(), encode("Tom"), None).unwrap();
+        put("WATCHED_DOG", "2".to_string(), encode("Max"), None).unwrap();
+
+        let mut watcher = watch("WATCHED_DOG", Some(after)).unwrap();
+
+        delete_eq("WATCHED_DOG", "1").unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut changes = vec![];
+
+        for _ in 0..2 {
+            changes.push(runtime.block_on(watcher.recv()).unwrap());
+        }
+
+        assert_eq!(
+            summary(&changes),
+            vec![("2".to_string(), true), ("1".to_string(), false)]
+        );
+    }
+
+    #[test]
+    fn prunes_changes_past_retention() {
+        let _serial = init();
+
+        set_change_retention(Retention {
+            max_changes: Some(10),
+            max_age: None,
+        });
+
+        // enough changes to cross a prune
+        let records = (0..PRUNE_INTERVAL)
+            .map(|i| (i.to_string(), encode("Rex"), None))
+            .collect();
+
+        let res = batch_put("PRUNED_DOG", records);
+        set_change_retention(Retention::default());
+        res.unwrap();
+
+        assert_eq!(oldest_change().unwrap(), Some(latest_seq() - 9));
+        assert!(watch("PRUNED_DOG", Some(0)).is_err());
+    }
+
+    #[test]
+    fn hides_expired_records() {
+        let _serial = init();
+        let now = now_millis() / 1000;
+
+        batch_put(
+            "EXPIRING_DOG",
+            vec![
+                ("1".to_string(), encode("Rex"), Some(now - 1)),
+                ("2".to_string(), encode("Max"), Some(now + 3600)),
+                ("3".to_string(), encode("Bo"), None),
+            ],
+        )
+        .unwrap();
+
+        let expired: Vec<String> = get_eq("EXPIRING_DOG", "1").unwrap();
+        assert!(expired.is_empty());
+
+        let dogs: Vec<String> = get_gte("EXPIRING_DOG", "").unwrap();
+        assert_eq!(dogs, vec!["Max".to_string(), "Bo".to_string()]);
+
+        let streamed = stream_begins_with::<String>("EXPIRING_DOG", "")
+            .unwrap()
+            .collect::<Result<Vec<String>, _>>()
+            .unwrap();
+        assert_eq!(streamed, dogs);
+
+        let expiries = scan("EXPIRING_DOG")
+            .unwrap()
+            .map(|record| record.map(|(pk, _, expires_at)| (pk, expires_at)))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            expiries,
+            vec![("2".to_string(), Some(now + 3600)), ("3".to_string(), None)]
+        );
+    }
+
+    #[test]
+    fn snapshot_reads_ignore_later_writes() {
+        let _serial = init();
+
+        put("SNAPSHOT_DOG", "1".to_string(), encode("Rex"), None).unwrap();
+
+        let id = begin_snapshot(Duration::from_secs(60)).unwrap();
+
+        put("SNAPSHOT_DOG", "1".to_string(), encode("Max"), None).unwrap();
+        put("SNAPSHOT_DOG", "2".to_string(), encode("Bo"), None).unwrap();
+
+        let pinned: Vec<String> = with_snapshot(&id, || get_gte("SNAPSHOT_DOG", "")).unwrap();
+        let streamed = with_snapshot(&id, || {
+            stream_gte::<String>("SNAPSHOT_DOG", "")?.collect::<Result<Vec<String>, _>>()
+        })
+        .unwrap();
+
+        end_snapshot(&id);
+
+        assert_eq!(pinned, vec!["Rex".to_string()]);
+        assert_eq!(streamed, pinned);
+
+        let latest: Vec<String> = get_gte("SNAPSHOT_DOG", "").unwrap();
+        assert_eq!(latest, vec!["Max".to_string(), "Bo".to_string()]);
+    }
+
+    #[test]
+    fn backs_up_records_and_change_log() {
+        let _serial = init();
+        let dir =
+            std::env::temp_dir().join(format!("bicycle_rocksdb_backup_{}", std::process::id()));
+
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        std::fs::create_dir_all(&dir).unwrap();
+
+        put("BACKED_UP_DOG", "1".to_string(), encode("Rex"), None).unwrap();
+
+        let seq = backup(&dir).unwrap();
+        assert_eq!(seq, latest_seq());
+
+        // left out of the copy
+        put("BACKED_UP_DOG", "2".to_string(), encode("Max"), None).unwrap();
+
+        let copy = DB::open_for_read_only(&Options::default(), dir.join(DB_PATH), false).unwrap();
+
+        let dog = copy.get("BACKED_UP_DOG#1").unwrap().unwrap();
+        assert_eq!(String::decode(&dog[..]).unwrap(), "Rex");
+        assert!(copy.get("BACKED_UP_DOG#2").unwrap().is_none());
+
+        assert!(copy.get(change_key(seq)).unwrap().is_some());
+        assert!(copy.get(change_key(seq + 1)).unwrap().is_none());
+
+        drop(copy);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ingests_scanned_records() {
+        let _serial = init();
+        let now = now_millis() / 1000;
+
+        batch_put(
+            "SCANNED_DOG",
+            vec![
+                ("1".to_string(), encode("Rex"), None),
+                ("2".to_string(), encode("Max"), Some(now + 3600)),
+                ("3".to_string(), encode("Bo"), Some(now - 1)),
+            ],
+        )
+        .unwrap();
+
+        let scanned = scan("SCANNED_DOG")
+            .unwrap()
+            .collect::<Result<Vec<RawRecord>, _>>()
+            .unwrap();
+        assert_eq!(scanned.len(), 2);
+
+        ingest("INGESTED_DOG", scanned.clone()).unwrap();
+
+        let ingested = scan("INGESTED_DOG")
+            .unwrap()
+            .collect::<Result<Vec<RawRecord>, _>>()
+            .unwrap();
+        assert_eq!(ingested, scanned);
+    }
+}
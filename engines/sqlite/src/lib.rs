@@ -20,39 +20,230 @@ along with this program.  If not, see <http://www.gnu.org/licenses/>.
 #[macro_use]
 extern crate lazy_static;
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::error::Error;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use parking_lot::Mutex;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+use r2d2_sqlite::rusqlite::backup::{Backup, StepResult};
 use r2d2_sqlite::rusqlite::params_from_iter;
-use r2d2_sqlite::rusqlite::Statement;
+use r2d2_sqlite::rusqlite::types::Value;
+use r2d2_sqlite::rusqlite::{Connection, Statement, Transaction};
 
+use r2d2::PooledConnection;
 use r2d2_sqlite::rusqlite;
 use r2d2_sqlite::SqliteConnectionManager;
 
 use log::{error, info};
 
+use serde::Deserialize;
+
+/// name of the engine, recorded in backups.
+pub const NAME: &str = "sqlite";
+
+const DB_PATH: &str = "__bicycle.engine.sqlite__";
+
+const PAGE_SIZE: usize = 256;
+
+/// the change log is pruned each time this many changes have been written.
+const PRUNE_INTERVAL: u64 = 1000;
+
+/// number of changes a watcher can fall behind by before it's disconnected.
+const WATCH_BUFFER_SIZE: usize = 10_000;
+const WATCH_REPLAY_PAGE_SIZE: usize = 1000;
+
+/// how often expired records are physically removed from `records`.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// how long a backup waits before retrying when the database is locked.
+const BACKUP_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// how often snapshots past their lease are released.
+const SNAPSHOT_REAP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// each leased snapshot holds a pooled connection until it's ended or expires,
+/// so only so many can be leased at once unless `Tuning::max_snapshots` says
+/// otherwise.
+const MAX_SNAPSHOTS: usize = 4;
+
+static SNAPSHOT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// snapshots leased with `begin_snapshot` that haven't been released yet.
+static LEASED_SNAPSHOTS: AtomicUsize = AtomicUsize::new(0);
+
+/// SQLite specific settings, the `[engine]` table of a server's `bicycle.toml`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tuning {
+    /// connections kept in the pool, r2d2's default of 10 when unset.
+    pub pool_size: Option<u32>,
+    /// snapshots that can be leased at once, each holding a connection.
+    /// snapshots pinned for a single call wait for a pooled connection instead.
+    pub max_snapshots: Option<usize>,
+}
+
+/// where and how the database is opened.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// directory the database is kept in, the working directory when empty.
+    pub data_dir: PathBuf,
+    pub tuning: Tuning,
+    /// opens an existing database for reading only, without taking it from
+    /// a server that has it open. a missing one is created as usual.
+    pub read_only: bool,
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/// sets where and how the database is opened, which has to happen before it's
+/// first used.
+pub fn configure(config: Config) -> Result<(), Box<dyn Error>> {
+    CONFIG
+        .set(config)
+        .map_err(|_| "the engine was already configured or opened".into())
+}
+
 lazy_static! {
     static ref SQLITE_POOL: r2d2::Pool<SqliteConnectionManager> = {
-        let manager = SqliteConnectionManager::file("__bicycle.engine.sqlite__");
+        let config = config();
 
-        let pool = r2d2::Pool::new(manager).expect("unable to create connection pool");
+        if !config.data_dir.as_os_str().is_empty() {
+            std::fs::create_dir_all(&config.data_dir).expect("unable to create data directory");
+        }
+
+        let path = config.data_dir.join(DB_PATH);
+        let read_only = config.read_only && path.exists();
+
+        let mut manager = SqliteConnectionManager::file(path);
+
+        if read_only {
+            manager = manager.with_flags(
+                rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY
+                    | rusqlite::OpenFlags::SQLITE_OPEN_URI
+                    | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            );
+        }
+
+        let mut builder = r2d2::Pool::builder();
+
+        if let Some(pool_size) = config.tuning.pool_size {
+            builder = builder.max_size(pool_size);
+        }
+
+        let pool = builder.build(manager).expect("unable to create connection pool");
+
+        // the server with it open keeps the tables and sweeps expired records
+        if read_only {
+            return pool;
+        }
 
         let conn = pool.get().expect("unable to get connection from pool");
 
+        // lets snapshot read transactions stay open without blocking writers
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .expect("unable to enable WAL journal mode");
+
         conn.execute(
             "CREATE TABLE IF NOT EXISTS records (
             pk TEXT PRIMARY KEY,
-            b BLOB NOT NULL
+            b BLOB NOT NULL,
+            expires_at INTEGER
         )",
             (),
         )
         .expect("unable to create 'records' table");
 
+        // databases created before record expiry was supported
+        let has_expires_at: bool = conn
+            .query_row(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info('records') WHERE name = 'expires_at'",
+                (),
+                |row| row.get(0),
+            )
+            .expect("unable to read 'records' table info");
+
+        if !has_expires_at {
+            conn.execute("ALTER TABLE records ADD COLUMN expires_at INTEGER", ())
+                .expect("unable to add 'expires_at' column");
+        }
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS records_expires_at ON records (expires_at)
+            WHERE expires_at IS NOT NULL",
+            (),
+        )
+        .expect("unable to create 'records_expires_at' index");
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS changes (
+            seq INTEGER PRIMARY KEY,
+            ts INTEGER NOT NULL,
+            model TEXT NOT NULL,
+            pk TEXT NOT NULL,
+            b BLOB
+        )",
+            (),
+        )
+        .expect("unable to create 'changes' table");
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS meta (
+            key TEXT PRIMARY KEY,
+            b BLOB NOT NULL
+        )",
+            (),
+        )
+        .expect("unable to create 'meta' table");
+
+        std::thread::spawn(sweep_expired);
+
         pool
     };
+    static ref CHANGES: Mutex<Changes> = Mutex::new(Changes::load());
+    static ref SNAPSHOTS: Mutex<HashMap<String, Arc<Snapshot>>> = {
+        std::thread::spawn(reap_snapshots);
+        Mutex::new(HashMap::new())
+    };
+}
+
+thread_local! {
+    static ACTIVE_SNAPSHOT: RefCell<Option<Arc<Snapshot>>> = const { RefCell::new(None) };
 }
 
 // HELPERS
 
+/// periodically deletes records past their `expires_at`. reads already skip
+/// them so this only reclaims space, and removals aren't in the change log.
+fn sweep_expired() {
+    loop {
+        std::thread::sleep(SWEEP_INTERVAL);
+
+        let res = SQLITE_POOL
+            .get()
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|conn| {
+                conn.execute("DELETE FROM records WHERE expires_at <= unixepoch()", ())
+                    .map_err(Box::<dyn Error>::from)
+            });
+
+        match res {
+            Ok(swept) => info!("sweep_expired {}", swept),
+            Err(err) => error!("failed to sweep expired records: {}", err),
+        }
+    }
+}
+
 fn get_from_statement<T>(stmt: &mut Statement, p: &[&str]) -> Result<Vec<T>, Box<dyn Error>>
 where
     T: prost::Message + Default,
@@ -81,46 +272,538 @@ where
     Ok(items)
 }
 
+fn delete_from_statement(model: &'static str, sql: &str, p: &[&str]) -> Result<(), Box<dyn Error>> {
+    let model_str = format!("{}#", model);
+
+    write_logged(model, |tx| {
+        let mut stmt = tx.prepare(sql)?;
+        let mut ops = vec![];
+
+        for key in stmt.query_map(params_from_iter(p), |row| row.get::<_, String>(0))? {
+            let key = key?;
+            ops.push((key[model_str.len()..].to_string(), Op::Delete));
+        }
+
+        Ok(ops)
+    })
+}
+
+/// runs `write` in a transaction along with change log entries for the ops
+/// it returns.
+fn write_logged<F>(model: &'static str, write: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnOnce(&Transaction) -> Result<Vec<(String, Op)>, Box<dyn Error>>,
+{
+    let mut changes = CHANGES.lock();
+    let mut conn = SQLITE_POOL.get()?;
+    let tx = conn.transaction()?;
+
+    let ops = write(&tx)?;
+    let logged = changes.log(model, ops);
+
+    {
+        let mut stmt =
+            tx.prepare("INSERT INTO changes (seq, ts, model, pk, b) VALUES (?1, ?2, ?3, ?4, ?5)")?;
+
+        for change in logged.iter() {
+            let record = match &change.op {
+                Op::Put(v) => Some(v),
+                Op::Delete => None,
+            };
+
+            stmt.execute((
+                change.seq as i64,
+                change.timestamp as i64,
+                &change.model,
+                &change.pk,
+                record,
+            ))?;
+        }
+    }
+
+    tx.commit()?;
+    changes.publish(logged);
+    Ok(())
+}
+
+// CHANGES
+
+#[derive(Clone, Debug)]
+pub enum Op {
+    Put(Vec<u8>),
+    Delete,
+}
+
+/// a committed write to a single record.
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub seq: u64,
+    /// milliseconds since the unix epoch.
+    pub timestamp: u64,
+    pub model: String,
+    pub pk: String,
+    pub op: Op,
+}
+
+/// how long the change log is kept for, changes outside either limit are
+/// pruned as new ones are written.
+#[derive(Clone, Debug)]
+pub struct Retention {
+    pub max_changes: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            max_changes: Some(1_000_000),
+            max_age: None,
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+struct Changes {
+    seq: u64,
+    retention: Retention,
+    watchers: Vec<(&'static str, Sender<Change>)>,
+}
+
+impl Changes {
+    fn load() -> Self {
+        let conn = SQLITE_POOL
+            .get()
+            .expect("unable to get connection from pool");
+
+        let seq: i64 = conn
+            .query_row("SELECT COALESCE(MAX(seq), 0) FROM changes", (), |row| {
+                row.get(0)
+            })
+            .expect("unable to read 'changes' table");
+
+        Changes {
+            seq: seq as u64,
+            retention: Retention::default(),
+            watchers: vec![],
+        }
+    }
+
+    /// assigns sequence numbers to `ops`, to be written with the records.
+    fn log(&mut self, model: &'static str, ops: Vec<(String, Op)>) -> Vec<Change> {
+        let timestamp = now_millis();
+
+        ops.into_iter()
+            .map(|(pk, op)| {
+                self.seq += 1;
+
+                Change {
+                    seq: self.seq,
+                    timestamp,
+                    model: model.to_string(),
+                    pk,
+                    op,
+                }
+            })
+            .collect()
+    }
+
+    fn publish(&mut self, logged: Vec<Change>) {
+        let before = self.seq - logged.len() as u64;
+
+        for change in logged {
+            self.watchers.retain(|(watched, watcher)| {
+                *watched != change.model || watcher.try_send(change.clone()).is_ok()
+            });
+        }
+
+        if before / PRUNE_INTERVAL != self.seq / PRUNE_INTERVAL {
+            if let Err(err) = self.prune() {
+                error!("failed to prune change log: {}", err);
+            }
+        }
+    }
+
+    fn prune(&self) -> Result<(), Box<dyn Error>> {
+        let conn = SQLITE_POOL.get()?;
+
+        if let Some(max_changes) = self.retention.max_changes {
+            conn.execute(
+                "DELETE FROM changes WHERE seq <= ?",
+                (self.seq.saturating_sub(max_changes) as i64,),
+            )?;
+        }
+
+        if let Some(max_age) = self.retention.max_age {
+            let cutoff = now_millis().saturating_sub(max_age.as_millis() as u64);
+            conn.execute("DELETE FROM changes WHERE ts < ?", (cutoff as i64,))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// sets how long the change log is kept for.
+pub fn set_change_retention(retention: Retention) {
+    CHANGES.lock().retention = retention;
+}
+
+/// reads up to `limit` changes, across all models, after `after`.
+pub fn get_changes(after: u64, limit: usize) -> Result<Vec<Change>, Box<dyn Error>> {
+    let conn = SQLITE_POOL.get()?;
+    let mut stmt = conn
+        .prepare("SELECT seq, ts, model, pk, b FROM changes WHERE seq > ? ORDER BY seq LIMIT ?")?;
+
+    let rows = stmt.query_map((after as i64, limit as i64), |row| {
+        let record: Option<Vec<u8>> = row.get(4)?;
+
+        Ok(Change {
+            seq: row.get::<_, i64>(0)? as u64,
+            timestamp: row.get::<_, i64>(1)? as u64,
+            model: row.get(2)?,
+            pk: row.get(3)?,
+            op: match record {
+                Some(v) => Op::Put(v),
+                None => Op::Delete,
+            },
+        })
+    })?;
+
+    let mut changes = vec![];
+
+    for change in rows {
+        changes.push(change?);
+    }
+
+    info!("get_changes");
+    Ok(changes)
+}
+
+fn oldest_change() -> Result<Option<u64>, Box<dyn Error>> {
+    Ok(get_changes(0, 1)?.first().map(|change| change.seq))
+}
+
+/// receives the changes made to a model, replaying the change log before
+/// switching over to live changes.
+pub struct Watcher {
+    model: &'static str,
+    cursor: u64,
+    until: u64,
+    backlog: std::vec::IntoIter<Change>,
+    live: Receiver<Change>,
+}
+
+impl Watcher {
+    /// waits for the next change, without holding a thread while it waits.
+    pub async fn recv(&mut self) -> Result<Change, Box<dyn Error>> {
+        loop {
+            if let Some(change) = self.backlog.next() {
+                return Ok(change);
+            }
+
+            if self.cursor >= self.until {
+                break;
+            }
+
+            let page = get_changes(self.cursor, WATCH_REPLAY_PAGE_SIZE)?;
+
+            self.cursor = match page.last() {
+                Some(last) => last.seq,
+                None => self.until,
+            };
+
+            self.backlog = page
+                .into_iter()
+                .filter(|change| change.model == self.model && change.seq <= self.until)
+                .collect::<Vec<Change>>()
+                .into_iter();
+        }
+
+        // publishing drops the sender once the buffer is full
+        match self.live.recv().await {
+            Some(change) => Ok(change),
+            None => Err("watcher fell behind, resume from the last received seq".into()),
+        }
+    }
+}
+
+/// watches for changes to `model`, replaying those after `after` first.
+pub fn watch(model: &'static str, after: Option<u64>) -> Result<Watcher, Box<dyn Error>> {
+    let mut changes = CHANGES.lock();
+    let (tx, rx) = channel(WATCH_BUFFER_SIZE);
+
+    let cursor = after.unwrap_or(changes.seq);
+
+    if cursor > changes.seq {
+        return Err(format!("seq {} is ahead of latest seq {}", cursor, changes.seq).into());
+    }
+
+    if cursor < changes.seq {
+        match oldest_change()? {
+            Some(oldest) if cursor + 1 >= oldest => {}
+            _ => {
+                return Err(format!("changes after seq {} are no longer available", cursor).into())
+            }
+        }
+    }
+
+    changes.watchers.push((model, tx));
+    info!("watch {}", model);
+
+    Ok(Watcher {
+        model,
+        cursor,
+        until: changes.seq,
+        backlog: vec![].into_iter(),
+        live: rx,
+    })
+}
+
+// SNAPSHOTS
+
+/// a consistent view of the database that reads can be pinned to, held open
+/// as a read transaction on a connection taken out of the pool.
+struct Snapshot {
+    conn: Mutex<PooledConnection<SqliteConnectionManager>>,
+    expires_at: Instant,
+    /// whether it counts against `max_snapshots`.
+    leased: bool,
+}
+
+impl Snapshot {
+    /// a snapshot for a single call, which isn't registered so its lease
+    /// isn't checked.
+    fn pinned() -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            conn: Mutex::new(Self::begin()?),
+            expires_at: Instant::now(),
+            leased: false,
+        })
+    }
+
+    fn leased(lease: Duration) -> Result<Self, Box<dyn Error>> {
+        let max_snapshots = config().tuning.max_snapshots.unwrap_or(MAX_SNAPSHOTS);
+
+        LEASED_SNAPSHOTS
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |leased| {
+                (leased < max_snapshots).then_some(leased + 1)
+            })
+            .map_err(|_| "too many open snapshots")?;
+
+        match Self::begin() {
+            Ok(conn) => Ok(Self {
+                conn: Mutex::new(conn),
+                expires_at: Instant::now() + lease,
+                leased: true,
+            }),
+            Err(err) => {
+                LEASED_SNAPSHOTS.fetch_sub(1, Ordering::AcqRel);
+                Err(err)
+            }
+        }
+    }
+
+    fn begin() -> Result<PooledConnection<SqliteConnectionManager>, Box<dyn Error>> {
+        let conn = SQLITE_POOL.get()?;
+
+        // WAL read transactions see the database as of their first read
+        conn.execute_batch("BEGIN")?;
+        conn.query_row("SELECT COUNT(*) FROM sqlite_master", (), |_| Ok(()))?;
+
+        Ok(conn)
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        if let Err(err) = self.conn.get_mut().execute_batch("ROLLBACK") {
+            error!("failed to release snapshot: {}", err);
+        }
+
+        if self.leased {
+            LEASED_SNAPSHOTS.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+fn active_snapshot() -> Option<Arc<Snapshot>> {
+    ACTIVE_SNAPSHOT.with(|active| active.borrow().clone())
+}
+
+/// runs `read` on the snapshot's connection when there is one, a pooled
+/// connection otherwise.
+fn read_from<F, R>(snapshot: &Option<Arc<Snapshot>>, read: F) -> Result<R, Box<dyn Error>>
+where
+    F: FnOnce(&Connection) -> Result<R, Box<dyn Error>>,
+{
+    match snapshot {
+        Some(snapshot) => read(&snapshot.conn.lock()),
+        None => read(&*SQLITE_POOL.get()?),
+    }
+}
+
+fn read<F, R>(read: F) -> Result<R, Box<dyn Error>>
+where
+    F: FnOnce(&Connection) -> Result<R, Box<dyn Error>>,
+{
+    read_from(&active_snapshot(), read)
+}
+
+fn reap_snapshots() {
+    loop {
+        std::thread::sleep(SNAPSHOT_REAP_INTERVAL);
+
+        let now = Instant::now();
+        SNAPSHOTS
+            .lock()
+            .retain(|_, snapshot| snapshot.expires_at > now);
+    }
+}
+
+/// pins a snapshot of the database until `end_snapshot` is called or `lease`
+/// runs out, returning its id.
+pub fn begin_snapshot(lease: Duration) -> Result<String, Box<dyn Error>> {
+    let id = format!(
+        "{:x}-{:x}",
+        now_millis(),
+        SNAPSHOT_COUNT.fetch_add(1, Ordering::Relaxed)
+    );
+
+    let snapshot = Snapshot::leased(lease)?;
+    SNAPSHOTS.lock().insert(id.clone(), Arc::new(snapshot));
+
+    info!("begin_snapshot {}", id);
+    Ok(id)
+}
+
+/// releases a snapshot, reads already in flight against it can finish.
+pub fn end_snapshot(id: &str) {
+    SNAPSHOTS.lock().remove(id);
+    info!("end_snapshot {}", id);
+}
+
+/// restores the previously active snapshot when dropped, including when a
+/// read panics.
+struct ActiveSnapshotGuard(Option<Arc<Snapshot>>);
+
+impl Drop for ActiveSnapshotGuard {
+    fn drop(&mut self) {
+        let prev = self.0.take();
+        ACTIVE_SNAPSHOT.with(|active| active.replace(prev));
+    }
+}
+
+fn with_active_snapshot<F, R>(snapshot: Arc<Snapshot>, read: F) -> Result<R, Box<dyn Error>>
+where
+    F: FnOnce() -> Result<R, Box<dyn Error>>,
+{
+    let _guard = ActiveSnapshotGuard(ACTIVE_SNAPSHOT.with(|active| active.replace(Some(snapshot))));
+
+    read()
+}
+
+/// runs `read` with every read on this thread pinned to snapshot `id`.
+pub fn with_snapshot<F, R>(id: &str, read: F) -> Result<R, Box<dyn Error>>
+where
+    F: FnOnce() -> Result<R, Box<dyn Error>>,
+{
+    let snapshot = SNAPSHOTS
+        .lock()
+        .get(id)
+        .filter(|snapshot| snapshot.expires_at > Instant::now())
+        .cloned()
+        .ok_or("snapshot not found or its lease has expired")?;
+
+    with_active_snapshot(snapshot, read)
+}
+
+/// runs `read` with every read on this thread pinned to a new snapshot,
+/// released when `read` returns.
+pub fn with_new_snapshot<F, R>(read: F) -> Result<R, Box<dyn Error>>
+where
+    F: FnOnce() -> Result<R, Box<dyn Error>>,
+{
+    let snapshot = Snapshot::pinned()?;
+
+    with_active_snapshot(Arc::new(snapshot), read)
+}
+
 // PUT
 
-pub fn put(model: &'static str, k: String, v: Vec<u8>) -> Result<(), Box<dyn Error>> {
-    SQLITE_POOL.get()?.execute(
-        "INSERT OR REPLACE INTO records (pk, b) VALUES (?1, ?2)",
-        (&format!("{}#{}", model, k), &v),
-    )?;
+pub fn put(
+    model: &'static str,
+    k: String,
+    v: Vec<u8>,
+    expires_at: Option<u64>,
+) -> Result<(), Box<dyn Error>> {
+    write_logged(model, |tx| {
+        tx.execute(
+            "INSERT OR REPLACE INTO records (pk, b, expires_at) VALUES (?1, ?2, ?3)",
+            (
+                &format!("{}#{}", model, k),
+                &v,
+                expires_at.map(|ts| ts as i64),
+            ),
+        )?;
+
+        Ok(vec![(k, Op::Put(v))])
+    })?;
     info!("put {}", model);
     Ok(())
 }
 
 pub fn batch_put(
     model: &'static str,
-    params: Vec<(String, Vec<u8>)>,
+    params: Vec<(String, Vec<u8>, Option<u64>)>,
 ) -> Result<(), Box<dyn Error>> {
-    let mut conn = SQLITE_POOL.get()?;
-    let tx = conn.transaction()?;
+    write_logged(model, |tx| {
+        let mut ops = vec![];
 
-    for (k, v) in params {
-        tx.execute(
-            "INSERT OR REPLACE INTO records (pk, b) VALUES (?1, ?2)",
-            (&format!("{}#{}", model, k), &v),
-        )?;
-    }
+        for (k, v, expires_at) in params {
+            tx.execute(
+                "INSERT OR REPLACE INTO records (pk, b, expires_at) VALUES (?1, ?2, ?3)",
+                (
+                    &format!("{}#{}", model, k),
+                    &v,
+                    expires_at.map(|ts| ts as i64),
+                ),
+            )?;
+            ops.push((k, Op::Put(v)));
+        }
 
-    tx.commit()?;
+        Ok(ops)
+    })?;
     info!("batch_put {}", model);
     Ok(())
 }
 
+pub fn ingest(
+    model: &'static str,
+    params: Vec<(String, Vec<u8>, Option<u64>)>,
+) -> Result<(), Box<dyn Error>> {
+    batch_put(model, params)?;
+    info!("ingest {}", model);
+    Ok(())
+}
+
 // GET
 
 pub fn get_eq<T>(model: &'static str, val: &str) -> Result<Vec<T>, Box<dyn Error>>
 where
     T: prost::Message + Default,
 {
-    let conn = SQLITE_POOL.get()?;
-    let mut stmt = conn.prepare("SELECT b FROM records WHERE pk = ?")?;
+    let res = read(|conn| {
+        let mut stmt = conn.prepare(
+            "SELECT b FROM records WHERE pk = ? AND (expires_at IS NULL OR expires_at > unixepoch())",
+        )?;
 
-    let res = get_from_statement(&mut stmt, &[&format!("{}#{}", model, val)])?;
+        get_from_statement(&mut stmt, &[&format!("{}#{}", model, val)])
+    })?;
     info!("get_eq {}", model);
     Ok(res)
 }
@@ -129,13 +812,16 @@ pub fn get_gte<T>(model: &'static str, val: &str) -> Result<Vec<T>, Box<dyn Erro
 where
     T: prost::Message + Default,
 {
-    let conn = SQLITE_POOL.get()?;
-    let mut stmt = conn.prepare("SELECT b FROM records WHERE pk >= ? AND pk LIKE ?")?;
+    let res = read(|conn| {
+        let mut stmt = conn.prepare(
+            "SELECT b FROM records WHERE pk >= ? AND pk LIKE ? AND (expires_at IS NULL OR expires_at > unixepoch())",
+        )?;
 
-    let res = get_from_statement(
-        &mut stmt,
-        &[&format!("{}#{}", model, val), &format!("{}#%", model)],
-    )?;
+        get_from_statement(
+            &mut stmt,
+            &[&format!("{}#{}", model, val), &format!("{}#%", model)],
+        )
+    })?;
     info!("get_gte {}", model);
     Ok(res)
 }
@@ -144,13 +830,16 @@ pub fn get_lte<T>(model: &'static str, val: &str) -> Result<Vec<T>, Box<dyn Erro
 where
     T: prost::Message + Default,
 {
-    let conn = SQLITE_POOL.get()?;
-    let mut stmt = conn.prepare("SELECT b FROM records WHERE pk <= ? AND pk LIKE ?")?;
+    let res = read(|conn| {
+        let mut stmt = conn.prepare(
+            "SELECT b FROM records WHERE pk <= ? AND pk LIKE ? AND (expires_at IS NULL OR expires_at > unixepoch())",
+        )?;
 
-    let res = get_from_statement(
-        &mut stmt,
-        &[&format!("{}#{}", model, val), &format!("{}#%", model)],
-    )?;
+        get_from_statement(
+            &mut stmt,
+            &[&format!("{}#{}", model, val), &format!("{}#%", model)],
+        )
+    })?;
     info!("get_lte {}", model);
     Ok(res)
 }
@@ -159,19 +848,256 @@ pub fn get_begins_with<T>(model: &'static str, val: &str) -> Result<Vec<T>, Box<
 where
     T: prost::Message + Default,
 {
-    let conn = SQLITE_POOL.get()?;
-    let mut stmt = conn.prepare("SELECT b FROM records WHERE pk LIKE ?")?;
+    let res = read(|conn| {
+        let mut stmt = conn.prepare(
+            "SELECT b FROM records WHERE pk LIKE ? AND (expires_at IS NULL OR expires_at > unixepoch())",
+        )?;
 
-    let res = get_from_statement(&mut stmt, &[&format!("{}#{}%", model, val)])?;
+        get_from_statement(&mut stmt, &[&format!("{}#{}%", model, val)])
+    })?;
     info!("get_begins_with {}", model);
     Ok(res)
 }
 
+// STREAM
+
+/// lazily decoded records, read from the engine as the iterator is advanced.
+pub type Records<T> = Box<dyn Iterator<Item = Result<T, Box<dyn Error>>>>;
+
+/// pages through `records` by primary key so that a pooled connection is
+/// only held while each page is being read.
+struct PagedRecords<T> {
+    snapshot: Option<Arc<Snapshot>>,
+    sql: &'static str,
+    params: Vec<String>,
+    cursor: String,
+    page: std::vec::IntoIter<Vec<u8>>,
+    done: bool,
+    _item: PhantomData<T>,
+}
+
+impl<T> PagedRecords<T> {
+    fn next_page(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut p: Vec<Value> = self.params.iter().cloned().map(Value::Text).collect();
+        p.push(Value::Text(self.cursor.clone()));
+        p.push(Value::Integer(PAGE_SIZE as i64));
+
+        let rows = read_from(&self.snapshot, |conn| {
+            let mut stmt = conn.prepare(self.sql)?;
+
+            let rows = stmt.query_map(params_from_iter(p), |row| {
+                let k: String = row.get(0)?;
+                let v: Vec<u8> = row.get(1)?;
+                Ok((k, v))
+            })?;
+
+            Ok(rows.collect::<Result<Vec<_>, _>>()?)
+        })?;
+
+        let mut page = vec![];
+
+        for (k, v) in rows {
+            self.cursor = k;
+            page.push(v);
+        }
+
+        self.done = page.len() < PAGE_SIZE;
+        self.page = page.into_iter();
+
+        Ok(())
+    }
+}
+
+impl<T> Iterator for PagedRecords<T>
+where
+    T: prost::Message + Default,
+{
+    type Item = Result<T, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let v = match self.page.next() {
+            Some(v) => v,
+            None => {
+                if self.done {
+                    return None;
+                }
+
+                if let Err(err) = self.next_page() {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+
+                self.page.next()?
+            }
+        };
+
+        match prost::Message::decode(&*v) {
+            Ok(item) => Some(Ok(item)),
+            Err(err) => {
+                error!("failed to decode record");
+                Some(Err(err.into()))
+            }
+        }
+    }
+}
+
+fn paged_records<T>(sql: &'static str, params: Vec<String>) -> Records<T>
+where
+    T: prost::Message + Default + 'static,
+{
+    Box::new(PagedRecords {
+        snapshot: active_snapshot(),
+        sql,
+        params,
+        cursor: "".to_string(),
+        page: vec![].into_iter(),
+        done: false,
+        _item: PhantomData,
+    })
+}
+
+/// a record as it's stored, without its model prefix: `(pk, encoded, expires_at)`.
+pub type RawRecord = (String, Vec<u8>, Option<u64>);
+
+struct ScanRecords {
+    snapshot: Option<Arc<Snapshot>>,
+    model: &'static str,
+    cursor: String,
+    page: std::vec::IntoIter<RawRecord>,
+    done: bool,
+}
+
+impl ScanRecords {
+    fn next_page(&mut self) -> Result<(), Box<dyn Error>> {
+        let prefix = format!("{}#", self.model);
+        // '$' follows '#', so this bounds the keys to those with the prefix
+        let end = format!("{}$", self.model);
+
+        let rows = read_from(&self.snapshot, |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT pk, b, expires_at FROM records WHERE pk >= ?1 AND pk < ?2 AND pk > ?3
+                AND (expires_at IS NULL OR expires_at > unixepoch())
+                ORDER BY pk LIMIT ?4",
+            )?;
+
+            let rows = stmt.query_map((&prefix, &end, &self.cursor, PAGE_SIZE as i64), |row| {
+                let k: String = row.get(0)?;
+                let v: Vec<u8> = row.get(1)?;
+                let expires_at: Option<i64> = row.get(2)?;
+                Ok((k, v, expires_at))
+            })?;
+
+            Ok(rows.collect::<Result<Vec<_>, _>>()?)
+        })?;
+
+        let mut page = vec![];
+
+        for (k, v, expires_at) in rows {
+            page.push((
+                k[prefix.len()..].to_string(),
+                v,
+                expires_at.map(|ts| ts as u64),
+            ));
+            self.cursor = k;
+        }
+
+        self.done = page.len() < PAGE_SIZE;
+        self.page = page.into_iter();
+
+        Ok(())
+    }
+}
+
+impl Iterator for ScanRecords {
+    type Item = Result<RawRecord, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(record) = self.page.next() {
+            return Some(Ok(record));
+        }
+
+        if self.done {
+            return None;
+        }
+
+        if let Err(err) = self.next_page() {
+            self.done = true;
+            return Some(Err(err));
+        }
+
+        self.page.next().map(Ok)
+    }
+}
+
+/// every live record of `model` in primary key order, still encoded. used to
+/// move records between engines, which `ingest` takes back in the same shape.
+pub fn scan(model: &'static str) -> Result<Records<RawRecord>, Box<dyn Error>> {
+    info!("scan {}", model);
+    Ok(Box::new(ScanRecords {
+        snapshot: active_snapshot(),
+        model,
+        cursor: "".to_string(),
+        page: vec![].into_iter(),
+        done: false,
+    }))
+}
+
+pub fn stream_eq<T>(model: &'static str, val: &str) -> Result<Records<T>, Box<dyn Error>>
+where
+    T: prost::Message + Default + 'static,
+{
+    let res = get_eq::<T>(model, val)?;
+    info!("stream_eq {}", model);
+    Ok(Box::new(res.into_iter().map(Ok)))
+}
+
+pub fn stream_gte<T>(model: &'static str, val: &str) -> Result<Records<T>, Box<dyn Error>>
+where
+    T: prost::Message + Default + 'static,
+{
+    let res = paged_records(
+        "SELECT pk, b FROM records WHERE pk >= ? AND pk LIKE ? AND pk > ? AND (expires_at IS NULL OR expires_at > unixepoch())
+        ORDER BY pk LIMIT ?",
+        vec![format!("{}#{}", model, val), format!("{}#%", model)],
+    );
+    info!("stream_gte {}", model);
+    Ok(res)
+}
+
+pub fn stream_lte<T>(model: &'static str, val: &str) -> Result<Records<T>, Box<dyn Error>>
+where
+    T: prost::Message + Default + 'static,
+{
+    // descending like RocksDB's reverse iterator, the cursor is empty until the
+    // first page is read
+    let res = paged_records(
+        "SELECT pk, b FROM records WHERE pk <= ?1 AND pk LIKE ?2 AND (?3 = '' OR pk < ?3) AND (expires_at IS NULL OR expires_at > unixepoch())
+        ORDER BY pk DESC LIMIT ?4",
+        vec![format!("{}#{}", model, val), format!("{}#%", model)],
+    );
+    info!("stream_lte {}", model);
+    Ok(res)
+}
+
+pub fn stream_begins_with<T>(model: &'static str, val: &str) -> Result<Records<T>, Box<dyn Error>>
+where
+    T: prost::Message + Default + 'static,
+{
+    let res = paged_records(
+        "SELECT pk, b FROM records WHERE pk LIKE ? AND pk > ? AND (expires_at IS NULL OR expires_at > unixepoch())
+        ORDER BY pk LIMIT ?",
+        vec![format!("{}#{}%", model, val)],
+    );
+    info!("stream_begins_with {}", model);
+    Ok(res)
+}
+
 // DELETE
 
 pub fn delete_eq(model: &'static str, val: &str) -> Result<(), Box<dyn Error>> {
-    SQLITE_POOL.get()?.execute(
-        "DELETE FROM records WHERE pk = ?",
+    delete_from_statement(
+        model,
+        "DELETE FROM records WHERE pk = ? RETURNING pk",
         &[&format!("{}#{}", model, val)],
     )?;
     info!("delete_eq {}", model);
@@ -179,8 +1105,9 @@ pub fn delete_eq(model: &'static str, val: &str) -> Result<(), Box<dyn Error>> {
 }
 
 pub fn delete_gte(model: &'static str, val: &str) -> Result<(), Box<dyn Error>> {
-    SQLITE_POOL.get()?.execute(
-        "DELETE FROM records WHERE pk >= ? AND pk LIKE ?",
+    delete_from_statement(
+        model,
+        "DELETE FROM records WHERE pk >= ? AND pk LIKE ? RETURNING pk",
         &[&format!("{}#{}", model, val), &format!("{}#%", model)],
     )?;
     info!("delete_gte {}", model);
@@ -188,8 +1115,9 @@ pub fn delete_gte(model: &'static str, val: &str) -> Result<(), Box<dyn Error>>
 }
 
 pub fn delete_lte(model: &'static str, val: &str) -> Result<(), Box<dyn Error>> {
-    SQLITE_POOL.get()?.execute(
-        "DELETE FROM records WHERE pk <= ? AND pk LIKE ?",
+    delete_from_statement(
+        model,
+        "DELETE FROM records WHERE pk <= ? AND pk LIKE ? RETURNING pk",
         &[&format!("{}#{}", model, val), &format!("{}#%", model)],
     )?;
     info!("delete_lte {}", model);
@@ -197,10 +1125,380 @@ pub fn delete_lte(model: &'static str, val: &str) -> Result<(), Box<dyn Error>>
 }
 
 pub fn delete_begins_with(model: &'static str, val: &str) -> Result<(), Box<dyn Error>> {
-    SQLITE_POOL.get()?.execute(
-        "DELETE FROM records WHERE pk LIKE ?",
+    delete_from_statement(
+        model,
+        "DELETE FROM records WHERE pk LIKE ? RETURNING pk",
         &[&format!("{}#{}%", model, val)],
     )?;
     info!("delete_begins_with {}", model);
     Ok(())
 }
+
+// META
+
+/// reads a value kept alongside the records, such as the schema version.
+/// meta isn't in the change log or visible to snapshots.
+pub fn get_meta(key: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let conn = SQLITE_POOL.get()?;
+    let mut stmt = conn.prepare("SELECT b FROM meta WHERE key = ?")?;
+    let mut rows = stmt.query_map([key], |row| row.get(0))?;
+
+    Ok(rows.next().transpose()?)
+}
+
+pub fn put_meta(key: &str, v: &[u8]) -> Result<(), Box<dyn Error>> {
+    SQLITE_POOL.get()?.execute(
+        "INSERT OR REPLACE INTO meta (key, b) VALUES (?1, ?2)",
+        (key, v),
+    )?;
+    info!("put_meta {}", key);
+    Ok(())
+}
+
+pub fn delete_meta(key: &str) -> Result<(), Box<dyn Error>> {
+    SQLITE_POOL
+        .get()?
+        .execute("DELETE FROM meta WHERE key = ?", [key])?;
+    info!("delete_meta {}", key);
+    Ok(())
+}
+
+/// every meta key and value.
+pub fn meta() -> Result<HashMap<String, Vec<u8>>, Box<dyn Error>> {
+    let conn = SQLITE_POOL.get()?;
+    let mut stmt = conn.prepare("SELECT key, b FROM meta")?;
+    let rows = stmt.query_map((), |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+    Ok(rows.collect::<Result<HashMap<_, _>, _>>()?)
+}
+
+// BACKUP
+
+/// copies a consistent snapshot of the database into `dir`, laid out the same
+/// as a server's working directory, using the SQLite online backup API.
+/// returns the seq of the latest change included in the copy.
+pub fn backup(dir: &Path) -> Result<u64, Box<dyn Error>> {
+    let conn = SQLITE_POOL.get()?;
+    let mut dest = Connection::open(dir.join(DB_PATH))?;
+
+    {
+        let backup = Backup::new(&conn, &mut dest)?;
+
+        // copying every page in one step holds a read lock for the duration,
+        // so writers wait rather than forcing the backup to restart
+        loop {
+            match backup.step(-1)? {
+                StepResult::Done => break,
+                _ => std::thread::sleep(BACKUP_RETRY_INTERVAL),
+            }
+        }
+    }
+
+    let seq: i64 = dest.query_row("SELECT COALESCE(MAX(seq), 0) FROM changes", (), |row| {
+        row.get(0)
+    })?;
+
+    info!("backup {}", seq);
+    Ok(seq as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Once;
+
+    use parking_lot::MutexGuard;
+    use prost::Message;
+
+    use super::*;
+
+    static INIT: Once = Once::new();
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    /// the engine is configured once per process, so tests share a database
+    /// and each keeps to models of its own. they run one at a time since the
+    /// change log, its retention and the snapshot limit are shared too.
+    fn init() -> MutexGuard<'static, ()> {
+        INIT.call_once(|| {
+            let data_dir =
+                std::env::temp_dir().join(format!("bicycle_sqlite_{}", std::process::id()));
+
+            if data_dir.exists() {
+                std::fs::remove_dir_all(&data_dir).unwrap();
+            }
+
+            configure(Config {
+                data_dir,
+                tuning: Tuning::default(),
+                read_only: false,
+            })
+            .unwrap();
+        });
+
+        SERIAL.lock()
+    }
+
+    fn encode(name: &str) -> Vec<u8> {
+        name.to_string().encode_to_vec()
+    }
+
+    fn latest_seq() -> u64 {
+        CHANGES.lock().seq
+    }
+
+    fn summary(changes: &[Change]) -> Vec<(String, bool)> {
+        changes
+            .iter()
+            .map(|change| (change.pk.clone(), matches!(change.op, Op::Put(_))))
+            .collect()
+    }
+
+    #[test]
+    fn logs_changes_after_seq() {
+        let _serial = init();
+        let after = latest_seq();
+
+        put("LOGGED_DOG", "1".to_string(), encode("Rex"), None).unwrap();
+        put("LOGGED_DOG", "2".to_string(), encode("Max"), None).unwrap();
+        delete_eq("LOGGED_DOG", "1").unwrap();
+        delete_eq("LOGGED_DOG", "3").unwrap();
+
+        let changes = get_changes(after, 10).unwrap();
+
+        assert_eq!(
+            summary(&changes),
+            vec![
+                ("1".to_string(), true),
+                ("2".to_string(), true),
+                ("1".to_string(), false),
+            ]
+        );
+        assert_eq!(changes.last().unwrap().seq, latest_seq());
+    }
+
+    #[test]
+    fn watch_resumes_after_seq() {
+        let _serial = init();
+
+        put("WATCHED_DOG", "1".to_string(), encode("Rex"), None).unwrap();
+        let after = latest_seq();
+
+        put("WATCHED_CAT", "1".to_string(), encode("Tom"), None).unwrap();
+        put("WATCHED_DOG", "2".to_string(), encode("Max"), None).unwrap();
+
+        let mut watcher = watch("WATCHED_DOG", Some(after)).unwrap();
+
+        delete_eq("WATCHED_DOG", "1").unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut changes = vec![];
+
+        for _ in 0..2 {
+            changes.push(runtime.block_on(watcher.recv()).unwrap());
+        }
+
+        assert_eq!(
+            summary(&changes),
+            vec![("2".to_string(), true), ("1".to_string(), false)]
+        );
+    }
+
+    #[test]
+    fn prunes_changes_past_retention() {
+        let _serial = init();
+
+        set_change_retention(Retention {
+            max_changes: Some(10),
+            max_age: None,
+        });
+
+        // enough changes to cross a prune
+        let records = (0..PRUNE_INTERVAL)
+            .map(|i| (i.to_string(), encode("Rex"), None))
+            .collect();
+
+        let res = batch_put("PRUNED_DOG", records);
+        set_change_retention(Retention::default());
+        res.unwrap();
+
+        assert_eq!(oldest_change().unwrap(), Some(latest_seq() - 9));
+        assert!(watch("PRUNED_DOG", Some(0)).is_err());
+    }
+
+    #[test]
+    fn hides_expired_records() {
+        let _serial = init();
+        let now = now_millis() / 1000;
+
+        batch_put(
+            "EXPIRING_DOG",
+            vec![
+                ("1".to_string(), encode("Rex"), Some(now - 1)),
+                ("2".to_string(), encode("Max"), Some(now + 3600)),
+                ("3".to_string(), encode("Bo"), None),
+            ],
+        )
+        .unwrap();
+
+        let expired: Vec<String> = get_eq("EXPIRING_DOG", "1").unwrap();
+        assert!(expired.is_empty());
+
+        let dogs: Vec<String> = get_gte("EXPIRING_DOG", "").unwrap();
+        assert_eq!(dogs, vec!["Max".to_string(), "Bo".to_string()]);
+
+        let streamed = stream_begins_with::<String>("EXPIRING_DOG", "")
+            .unwrap()
+            .collect::<Result<Vec<String>, _>>()
+            .unwrap();
+        assert_eq!(streamed, dogs);
+
+        let expiries = scan("EXPIRING_DOG")
+            .unwrap()
+            .map(|record| record.map(|(pk, _, expires_at)| (pk, expires_at)))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            expiries,
+            vec![("2".to_string(), Some(now + 3600)), ("3".to_string(), None)]
+        );
+    }
+
+    #[test]
+    fn snapshot_reads_ignore_later_writes() {
+        let _serial = init();
+
+        put("SNAPSHOT_DOG", "1".to_string(), encode("Rex"), None).unwrap();
+
+        let id = begin_snapshot(Duration::from_secs(60)).unwrap();
+
+        put("SNAPSHOT_DOG", "1".to_string(), encode("Max"), None).unwrap();
+        put("SNAPSHOT_DOG", "2".to_string(), encode("Bo"), None).unwrap();
+
+        let pinned: Vec<String> = with_snapshot(&id, || get_gte("SNAPSHOT_DOG", "")).unwrap();
+        let streamed = with_snapshot(&id, || {
+            stream_gte::<String>("SNAPSHOT_DOG", "")?.collect::<Result<Vec<String>, _>>()
+        })
+        .unwrap();
+
+        end_snapshot(&id);
+
+        assert_eq!(pinned, vec!["Rex".to_string()]);
+        assert_eq!(streamed, pinned);
+
+        let latest: Vec<String> = get_gte("SNAPSHOT_DOG", "").unwrap();
+        assert_eq!(latest, vec!["Max".to_string(), "Bo".to_string()]);
+    }
+
+    #[test]
+    fn backs_up_records_and_change_log() {
+        let _serial = init();
+        let dir =
+            std::env::temp_dir().join(format!("bicycle_sqlite_backup_{}", std::process::id()));
+
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        std::fs::create_dir_all(&dir).unwrap();
+
+        put("BACKED_UP_DOG", "1".to_string(), encode("Rex"), None).unwrap();
+
+        let seq = backup(&dir).unwrap();
+        assert_eq!(seq, latest_seq());
+
+        // left out of the copy
+        put("BACKED_UP_DOG", "2".to_string(), encode("Max"), None).unwrap();
+
+        let copy = Connection::open(dir.join(DB_PATH)).unwrap();
+
+        let dogs: Vec<String> = get_from_statement(
+            &mut copy
+                .prepare("SELECT b FROM records WHERE pk LIKE ? ORDER BY pk")
+                .unwrap(),
+            &["BACKED_UP_DOG#%"],
+        )
+        .unwrap();
+        assert_eq!(dogs, vec!["Rex".to_string()]);
+
+        let copied_seq: i64 = copy
+            .query_row("SELECT MAX(seq) FROM changes", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(copied_seq as u64, seq);
+
+        drop(copy);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ingests_scanned_records() {
+        let _serial = init();
+        let now = now_millis() / 1000;
+
+        batch_put(
+            "SCANNED_DOG",
+            vec![
+                ("1".to_string(), encode("Rex"), None),
+                ("2".to_string(), encode("Max"), Some(now + 3600)),
+                ("3".to_string(), encode("Bo"), Some(now - 1)),
+            ],
+        )
+        .unwrap();
+
+        let scanned = scan("SCANNED_DOG")
+            .unwrap()
+            .collect::<Result<Vec<RawRecord>, _>>()
+            .unwrap();
+        assert_eq!(scanned.len(), 2);
+
+        ingest("INGESTED_DOG", scanned.clone()).unwrap();
+
+        let ingested = scan("INGESTED_DOG")
+            .unwrap()
+            .collect::<Result<Vec<RawRecord>, _>>()
+            .unwrap();
+        assert_eq!(ingested, scanned);
+    }
+
+    #[test]
+    fn streams_lte_in_descending_order() {
+        let _serial = init();
+
+        let records = (1..=3)
+            .map(|i| (i.to_string(), encode(&i.to_string()), None))
+            .collect();
+        batch_put("LTE_DOG", records).unwrap();
+
+        let got: Vec<String> = get_lte("LTE_DOG", "2").unwrap();
+        assert_eq!(got, vec!["1".to_string(), "2".to_string()]);
+
+        let streamed = stream_lte::<String>("LTE_DOG", "2")
+            .unwrap()
+            .collect::<Result<Vec<String>, _>>()
+            .unwrap();
+        assert_eq!(streamed, vec!["2".to_string(), "1".to_string()]);
+    }
+
+    #[test]
+    fn pinned_snapshots_dont_count_against_leases() {
+        let _serial = init();
+
+        let lease = Duration::from_secs(60);
+        let ids: Vec<String> = (0..MAX_SNAPSHOTS)
+            .map(|_| begin_snapshot(lease).unwrap())
+            .collect();
+
+        assert!(begin_snapshot(lease).is_err());
+
+        put("PINNED_DOG", "1".to_string(), encode("Rex"), None).unwrap();
+
+        let dogs: Vec<String> = with_new_snapshot(|| get_eq("PINNED_DOG", "1")).unwrap();
+        assert_eq!(dogs, vec!["Rex".to_string()]);
+
+        for id in ids.iter() {
+            end_snapshot(id);
+        }
+
+        end_snapshot(&begin_snapshot(lease).unwrap());
+    }
+}
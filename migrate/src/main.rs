@@ -0,0 +1,267 @@
+/*
+BicycleDB is a protobuf-defined database management system.
+
+Copyright (C) 2024 Ordinary Labs
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::Instant;
+
+type Records = Box<dyn Iterator<Item = Result<(String, Vec<u8>, Option<u64>), Box<dyn Error>>>>;
+type Scan = fn(&'static str) -> Result<Records, Box<dyn Error>>;
+type Ingest = fn(&'static str, Vec<(String, Vec<u8>, Option<u64>)>) -> Result<(), Box<dyn Error>>;
+type Meta = fn() -> Result<HashMap<String, Vec<u8>>, Box<dyn Error>>;
+type PutMeta = fn(&str, &[u8]) -> Result<(), Box<dyn Error>>;
+type Configure = fn(PathBuf) -> Result<(), Box<dyn Error>>;
+
+struct Engine {
+    name: &'static str,
+    configure: Configure,
+    scan: Scan,
+    ingest: Ingest,
+    meta: Meta,
+    put_meta: PutMeta,
+}
+
+const ENGINES: &[Engine] = &[
+    Engine {
+        name: bicycle_rocksdb::NAME,
+        configure: |data_dir| {
+            bicycle_rocksdb::configure(bicycle_rocksdb::Config {
+                data_dir,
+                ..Default::default()
+            })
+        },
+        scan: bicycle_rocksdb::scan,
+        ingest: bicycle_rocksdb::ingest,
+        meta: bicycle_rocksdb::meta,
+        put_meta: bicycle_rocksdb::put_meta,
+    },
+    Engine {
+        name: bicycle_sqlite::NAME,
+        configure: |data_dir| {
+            bicycle_sqlite::configure(bicycle_sqlite::Config {
+                data_dir,
+                ..Default::default()
+            })
+        },
+        scan: bicycle_sqlite::scan,
+        ingest: bicycle_sqlite::ingest,
+        meta: bicycle_sqlite::meta,
+        put_meta: bicycle_sqlite::put_meta,
+    },
+];
+
+const MODELS: &[&str] = &[
+    // ##START_MIGRATE_MODELS##
+    "EXAMPLE",
+    // ##END_MIGRATE_MODELS##
+];
+
+/// read from the working directory when `--config` isn't passed, like the
+/// server does.
+const DEFAULT_CONFIG: &str = "bicycle.toml";
+
+/// records are written to the destination in batches of this many.
+const BATCH_SIZE: usize = 10_000;
+
+/// record count and an order independent checksum of a model's records.
+#[derive(Default, PartialEq)]
+struct Tally {
+    count: u64,
+    checksum: u64,
+}
+
+impl Tally {
+    fn add(&mut self, record: &(String, Vec<u8>, Option<u64>)) {
+        // both engines are hashed within this process, so the default hasher
+        // is stable enough to compare them
+        let mut hasher = DefaultHasher::new();
+        record.hash(&mut hasher);
+
+        self.count += 1;
+        self.checksum = self.checksum.wrapping_add(hasher.finish());
+    }
+}
+
+fn find_engine(name: &str) -> Result<&'static Engine, Box<dyn Error>> {
+    Ok(ENGINES
+        .iter()
+        .find(|engine| engine.name == name)
+        .ok_or_else(|| format!("no engine named '{}'", name))?)
+}
+
+fn tally(engine: &Engine, model: &'static str) -> Result<Tally, Box<dyn Error>> {
+    let mut tally = Tally::default();
+
+    for record in (engine.scan)(model)? {
+        tally.add(&record?);
+    }
+
+    Ok(tally)
+}
+
+/// streams every record of `model` from `from` into `to` in batches.
+fn copy(from: &Engine, to: &Engine, model: &'static str) -> Result<Tally, Box<dyn Error>> {
+    let mut tally = Tally::default();
+    let mut batch = vec![];
+
+    for record in (from.scan)(model)? {
+        let record = record?;
+        tally.add(&record);
+        batch.push(record);
+
+        if batch.len() >= BATCH_SIZE {
+            (to.ingest)(model, std::mem::take(&mut batch))?;
+        }
+    }
+
+    if !batch.is_empty() {
+        (to.ingest)(model, batch)?;
+    }
+
+    Ok(tally)
+}
+
+fn migrate(from: &Engine, to: &Engine) -> Result<(), Box<dyn Error>> {
+    // copying into records that already exist would make the counts meaningless
+    for &model in MODELS {
+        if (to.scan)(model)?.next().is_some() {
+            return Err(format!("{} already has {} records", to.name, model).into());
+        }
+    }
+
+    let mut mismatched = vec![];
+
+    for &model in MODELS {
+        let now = Instant::now();
+
+        let copied = copy(from, to, model)?;
+        let written = tally(to, model)?;
+
+        println!(
+            "🚚 {}: {} records [{}ms]",
+            model,
+            copied.count,
+            now.elapsed().as_millis()
+        );
+
+        if copied != written {
+            eprintln!(
+                "❌ {}: {} records with checksum {:016x} in {}, {} with checksum {:016x} in {}",
+                model,
+                copied.count,
+                copied.checksum,
+                from.name,
+                written.count,
+                written.checksum,
+                to.name
+            );
+            mismatched.push(model);
+        }
+    }
+
+    if !mismatched.is_empty() {
+        return Err(format!("verification failed for {}", mismatched.join(", ")).into());
+    }
+
+    // carries over the schema version so migrations aren't run again
+    for (key, v) in (from.meta)()? {
+        (to.put_meta)(&key, &v)?;
+    }
+
+    Ok(())
+}
+
+/// the server's data directory, from `--data-dir` or the `data_dir` of its
+/// config file, which is relative to the file.
+fn data_dir(config: Option<&str>, data_dir: Option<&str>) -> Result<PathBuf, Box<dyn Error>> {
+    if let Some(data_dir) = data_dir {
+        return Ok(PathBuf::from(data_dir));
+    }
+
+    let path = match config {
+        Some(config) => Path::new(config),
+        None if Path::new(DEFAULT_CONFIG).exists() => Path::new(DEFAULT_CONFIG),
+        None => return Ok(PathBuf::new()),
+    };
+
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+    let config: toml::Table = content
+        .parse()
+        .map_err(|err| format!("invalid config {}: {}", path.display(), err))?;
+
+    match config.get("data_dir") {
+        Some(toml::Value::String(data_dir)) => {
+            Ok(path.parent().unwrap_or(Path::new("")).join(data_dir))
+        }
+        Some(_) => Err(format!("data_dir of {} isn't a string", path.display()).into()),
+        None => Ok(PathBuf::new()),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: bicycle_migrate <FROM> <TO> [--config <PATH>] [--data-dir <DIR>], where FROM and TO are different engines"
+    );
+    exit(2);
+}
+
+fn main() {
+    let mut engines = vec![];
+    let mut config = None;
+    let mut data_dir_arg = None;
+
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => config = Some(args.next().unwrap_or_else(|| usage())),
+            "--data-dir" => data_dir_arg = Some(args.next().unwrap_or_else(|| usage())),
+            _ => engines.push(arg),
+        }
+    }
+
+    let (from, to) = match &engines[..] {
+        [from, to] if from != to => (from, to),
+        _ => usage(),
+    };
+
+    let res = find_engine(from)
+        .and_then(|from| Ok((from, find_engine(to)?)))
+        .and_then(|(from, to)| {
+            // both stores are kept in the server's data directory
+            let data_dir = data_dir(config.as_deref(), data_dir_arg.as_deref())?;
+
+            (from.configure)(data_dir.clone())?;
+            (to.configure)(data_dir)?;
+
+            Ok((from, to))
+        });
+
+    if let Err(err) = res.and_then(|(from, to)| migrate(from, to)) {
+        eprintln!("failed to migrate from {} to {}: {}", from, to, err);
+        exit(1);
+    }
+}
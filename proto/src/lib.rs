@@ -17,5 +17,7 @@ You should have received a copy of the GNU Affero General Public License
 along with this program.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+// ##START_INCLUDE_PROTO##
 tonic::include_proto!("bicycle");
+// ##END_INCLUDE_PROTO##
 pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("bicycle_descriptor");
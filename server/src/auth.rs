@@ -0,0 +1,621 @@
+/*
+BicycleDB is a protobuf-defined database management system.
+
+Copyright (C) 2024 Ordinary Labs
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+// interceptors and handlers fail with tonic's `Status`, however large it is
+#![allow(clippy::result_large_err)]
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use tonic::{Request, Status};
+
+/// what a role can do to a model's records.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ModelOp {
+    /// `Get*`, `Stream*` and `Watch*` RPCs
+    Read,
+    /// `Put*`, `BatchPut*` and `Ingest*` RPCs
+    Write,
+    /// `Delete*` RPCs
+    Delete,
+}
+
+/// what a role can do with Biplane functions.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum BiplaneOp {
+    /// deploying stored functions and invoking one-off functions, which run
+    /// whatever code they're sent
+    Deploy,
+    Remove,
+    /// listing and invoking stored functions
+    Invoke,
+}
+
+/// permissions granted to whoever holds the role.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Role {
+    /// operations allowed per model, `*` for every model.
+    models: BTreeMap<String, Vec<ModelOp>>,
+    biplane: Vec<BiplaneOp>,
+    /// backup, export and import through the `Admin` service.
+    admin: bool,
+}
+
+/// a static API token and the roles it's granted.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct ApiToken {
+    token: String,
+    roles: Vec<String>,
+}
+
+/// verifies JWTs signed with a local key, granting the roles in their
+/// `roles_claim`.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Jwt {
+    #[serde(default = "default_algorithm")]
+    algorithm: Algorithm,
+    /// shared secret of the HMAC algorithms.
+    secret: Option<String>,
+    /// PEM public key of the RSA, ECDSA and EdDSA algorithms.
+    public_key: Option<PathBuf>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    /// claim holding the roles, as a list or a space separated string.
+    #[serde(default = "default_roles_claim")]
+    roles_claim: String,
+}
+
+fn default_algorithm() -> Algorithm {
+    Algorithm::HS256
+}
+
+fn default_roles_claim() -> String {
+    "roles".to_string()
+}
+
+/// the `[auth]` table of a `bicycle.toml`, whose paths are relative to the
+/// file.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct AuthConfig {
+    tokens: Vec<ApiToken>,
+    jwt: Option<Jwt>,
+    roles: BTreeMap<String, Role>,
+}
+
+impl AuthConfig {
+    pub(crate) fn resolve_paths(&mut self, dir: &Path) {
+        if let Some(jwt) = self.jwt.as_mut() {
+            jwt.public_key = jwt.public_key.take().map(|path| dir.join(path));
+        }
+    }
+}
+
+struct Auth {
+    tokens: Vec<ApiToken>,
+    jwt: Option<(Jwt, DecodingKey, Validation)>,
+    roles: BTreeMap<String, Role>,
+}
+
+/// `None` when auth is disabled.
+static AUTH: OnceLock<Option<Auth>> = OnceLock::new();
+
+/// roles of the caller, added to each request by `authenticate`.
+#[derive(Debug, Clone)]
+struct Principal {
+    roles: Vec<String>,
+}
+
+/// enables auth for every request when `config` has tokens or a JWT key,
+/// otherwise every request is allowed.
+pub(crate) fn init(config: Option<AuthConfig>) -> Result<bool, Box<dyn Error>> {
+    let auth = Auth::new(config)?;
+    let enabled = auth.is_some();
+
+    AUTH.set(auth).map_err(|_| "auth was already initialized")?;
+
+    Ok(enabled)
+}
+
+fn decoding_key(jwt: &Jwt) -> Result<DecodingKey, Box<dyn Error>> {
+    let pem = match (&jwt.secret, &jwt.public_key) {
+        (Some(secret), None) => return Ok(DecodingKey::from_secret(secret.as_bytes())),
+        (None, Some(path)) => {
+            fs::read(path).map_err(|err| format!("failed to read {}: {}", path.display(), err))?
+        }
+        _ => return Err("auth.jwt needs either a secret or a public_key".into()),
+    };
+
+    let key = match jwt.algorithm {
+        Algorithm::RS256
+        | Algorithm::RS384
+        | Algorithm::RS512
+        | Algorithm::PS256
+        | Algorithm::PS384
+        | Algorithm::PS512 => DecodingKey::from_rsa_pem(&pem)?,
+        Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(&pem)?,
+        Algorithm::EdDSA => DecodingKey::from_ed_pem(&pem)?,
+        algorithm => {
+            return Err(format!(
+                "{:?} is verified with a secret, not a public_key",
+                algorithm
+            )
+            .into())
+        }
+    };
+
+    Ok(key)
+}
+
+/// compares in constant time, so tokens can't be guessed from how long a
+/// comparison takes.
+fn token_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+impl Auth {
+    /// `None` when `config` has neither tokens nor a JWT key.
+    fn new(config: Option<AuthConfig>) -> Result<Option<Self>, Box<dyn Error>> {
+        let config = match config {
+            Some(config) if !config.tokens.is_empty() || config.jwt.is_some() => config,
+            _ => return Ok(None),
+        };
+
+        for token in config.tokens.iter() {
+            if let Some(role) = token.roles.iter().find(|r| !config.roles.contains_key(*r)) {
+                return Err(format!("an API token is granted unknown role '{}'", role).into());
+            }
+        }
+
+        let jwt = match config.jwt {
+            Some(jwt) => {
+                let key = decoding_key(&jwt)?;
+
+                let mut validation = Validation::new(jwt.algorithm);
+
+                if let Some(issuer) = jwt.issuer.as_deref() {
+                    validation.set_issuer(&[issuer]);
+                }
+
+                match jwt.audience.as_deref() {
+                    Some(audience) => validation.set_audience(&[audience]),
+                    None => validation.validate_aud = false,
+                }
+
+                Some((jwt, key, validation))
+            }
+            None => None,
+        };
+
+        Ok(Some(Auth {
+            tokens: config.tokens,
+            jwt,
+            roles: config.roles,
+        }))
+    }
+
+    /// roles granted by a static token or a valid JWT.
+    fn roles(&self, token: &str) -> Option<Vec<String>> {
+        let mut roles = None;
+
+        // every token is compared so the time taken doesn't say which matched
+        for api_token in self.tokens.iter() {
+            if token_eq(&api_token.token, token) {
+                roles = Some(api_token.roles.clone());
+            }
+        }
+
+        if roles.is_some() {
+            return roles;
+        }
+
+        let (jwt, key, validation) = self.jwt.as_ref()?;
+
+        let claims = jsonwebtoken::decode::<serde_json::Value>(token, key, validation)
+            .ok()?
+            .claims;
+
+        match claims.get(&jwt.roles_claim)? {
+            serde_json::Value::Array(roles) => Some(
+                roles
+                    .iter()
+                    .filter_map(|role| role.as_str())
+                    .map(|role| role.to_string())
+                    .collect(),
+            ),
+            serde_json::Value::String(roles) => Some(
+                roles
+                    .split_whitespace()
+                    .map(|role| role.to_string())
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    fn allows(&self, req: &Request<impl Sized>, allowed: impl Fn(&Role) -> bool) -> bool {
+        let Some(principal) = req.extensions().get::<Principal>() else {
+            return false;
+        };
+
+        principal
+            .roles
+            .iter()
+            .filter_map(|role| self.roles.get(role))
+            .any(allowed)
+    }
+}
+
+impl Auth {
+    fn authenticate(&self, mut req: Request<()>) -> Result<Request<()>, Status> {
+        let token = req
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("missing bearer token"))?;
+
+        let roles = self
+            .roles(token.trim())
+            .ok_or_else(|| Status::unauthenticated("invalid token"))?;
+
+        req.extensions_mut().insert(Principal { roles });
+
+        Ok(req)
+    }
+
+    fn authorize_model<T>(&self, req: &Request<T>, model: &str, op: ModelOp) -> Result<(), Status> {
+        let allows = |ops: Option<&Vec<ModelOp>>| ops.is_some_and(|ops| ops.contains(&op));
+
+        if self.allows(req, |role| {
+            allows(role.models.get(model)) || allows(role.models.get("*"))
+        }) {
+            return Ok(());
+        }
+
+        let op = format!("{:?}", op).to_lowercase();
+
+        match model {
+            "*" => Err(denied(format!("{} every model", op))),
+            model => Err(denied(format!("{} '{}'", op, model))),
+        }
+    }
+
+    fn authorize_biplane<T>(&self, req: &Request<T>, op: BiplaneOp) -> Result<(), Status> {
+        if self.allows(req, |role| role.biplane.contains(&op)) {
+            Ok(())
+        } else {
+            Err(denied(format!("{:?} functions", op).to_lowercase()))
+        }
+    }
+
+    fn authorize_admin<T>(&self, req: &Request<T>) -> Result<(), Status> {
+        if self.allows(req, |role| role.admin) {
+            Ok(())
+        } else {
+            Err(denied("use the admin service".to_string()))
+        }
+    }
+}
+
+fn denied(action: String) -> Status {
+    Status::permission_denied(format!("not allowed to {}", action))
+}
+
+fn auth() -> Option<&'static Auth> {
+    AUTH.get().and_then(|auth| auth.as_ref())
+}
+
+/// interceptor that checks the `authorization: Bearer <token>` header of each
+/// request when auth is enabled, see `init`.
+pub(crate) fn authenticate(req: Request<()>) -> Result<Request<()>, Status> {
+    match auth() {
+        Some(auth) => auth.authenticate(req),
+        None => Ok(req),
+    }
+}
+
+/// checks the caller may `op` the records of `model`, `*` asks for every
+/// model.
+pub(crate) fn authorize_model<T>(req: &Request<T>, model: &str, op: ModelOp) -> Result<(), Status> {
+    auth().map_or(Ok(()), |auth| auth.authorize_model(req, model, op))
+}
+
+/// checks the caller may `op` Biplane functions.
+pub(crate) fn authorize_biplane<T>(req: &Request<T>, op: BiplaneOp) -> Result<(), Status> {
+    auth().map_or(Ok(()), |auth| auth.authorize_biplane(req, op))
+}
+
+/// checks the caller may use the `Admin` service.
+pub(crate) fn authorize_admin<T>(req: &Request<T>) -> Result<(), Status> {
+    auth().map_or(Ok(()), |auth| auth.authorize_admin(req))
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+    use tonic::Code;
+
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [[tokens]]
+        token = "reader-token"
+        roles = ["reader"]
+
+        [[tokens]]
+        token = "admin-token"
+        roles = ["admin"]
+
+        [jwt]
+        secret = "secret"
+        issuer = "bicycle"
+
+        [roles.reader]
+        models = { Dog = ["read"] }
+
+        [roles.writer]
+        models = { "*" = ["read", "write"] }
+        biplane = ["invoke"]
+
+        [roles.admin]
+        admin = true
+    "#;
+
+    fn auth() -> Auth {
+        Auth::new(Some(toml::from_str(CONFIG).unwrap()))
+            .unwrap()
+            .unwrap()
+    }
+
+    fn request(authorization: Option<&str>) -> Request<()> {
+        let mut req = Request::new(());
+
+        if let Some(authorization) = authorization {
+            req.metadata_mut()
+                .insert("authorization", authorization.parse().unwrap());
+        }
+
+        req
+    }
+
+    fn authenticate(auth: &Auth, token: &str) -> Result<Request<()>, Status> {
+        auth.authenticate(request(Some(&format!("Bearer {}", token))))
+    }
+
+    fn jwt(claims: serde_json::Value) -> String {
+        jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap()
+    }
+
+    fn expires_in(secs: i64) -> i64 {
+        jsonwebtoken::get_current_timestamp() as i64 + secs
+    }
+
+    fn assert_status<T: std::fmt::Debug>(res: Result<T, Status>, code: Code, message: &str) {
+        let status = res.unwrap_err();
+
+        assert_eq!(status.code(), code);
+        assert_eq!(status.message(), message);
+    }
+
+    #[test]
+    fn disabled_without_tokens_or_jwt() {
+        assert!(Auth::new(None).unwrap().is_none());
+        assert!(Auth::new(Some(AuthConfig::default())).unwrap().is_none());
+    }
+
+    #[test]
+    fn allows_ops_per_model() {
+        let auth = auth();
+        let req = authenticate(&auth, "reader-token").unwrap();
+
+        assert!(auth.authorize_model(&req, "Dog", ModelOp::Read).is_ok());
+        assert_status(
+            auth.authorize_model(&req, "Dog", ModelOp::Write),
+            Code::PermissionDenied,
+            "not allowed to write 'Dog'",
+        );
+        assert_status(
+            auth.authorize_model(&req, "Cat", ModelOp::Read),
+            Code::PermissionDenied,
+            "not allowed to read 'Cat'",
+        );
+    }
+
+    #[test]
+    fn wildcard_covers_every_model() {
+        let auth = auth();
+
+        let writer = jwt(json!({ "iss": "bicycle", "exp": expires_in(60), "roles": ["writer"] }));
+        let req = authenticate(&auth, &writer).unwrap();
+
+        assert!(auth.authorize_model(&req, "Cat", ModelOp::Write).is_ok());
+        assert!(auth.authorize_model(&req, "*", ModelOp::Read).is_ok());
+        assert_status(
+            auth.authorize_model(&req, "Cat", ModelOp::Delete),
+            Code::PermissionDenied,
+            "not allowed to delete 'Cat'",
+        );
+
+        // reading one model doesn't grant reading every model
+        let req = authenticate(&auth, "reader-token").unwrap();
+
+        assert_status(
+            auth.authorize_model(&req, "*", ModelOp::Read),
+            Code::PermissionDenied,
+            "not allowed to read every model",
+        );
+    }
+
+    #[test]
+    fn authorizes_biplane_and_admin() {
+        let auth = auth();
+
+        let writer = jwt(json!({ "iss": "bicycle", "exp": expires_in(60), "roles": ["writer"] }));
+        let req = authenticate(&auth, &writer).unwrap();
+
+        assert!(auth.authorize_biplane(&req, BiplaneOp::Invoke).is_ok());
+        assert_status(
+            auth.authorize_biplane(&req, BiplaneOp::Deploy),
+            Code::PermissionDenied,
+            "not allowed to deploy functions",
+        );
+        assert_status(
+            auth.authorize_admin(&req),
+            Code::PermissionDenied,
+            "not allowed to use the admin service",
+        );
+
+        let req = authenticate(&auth, "admin-token").unwrap();
+
+        assert!(auth.authorize_admin(&req).is_ok());
+    }
+
+    #[test]
+    fn rejects_missing_bearer_token() {
+        let auth = auth();
+
+        assert_status(
+            auth.authenticate(request(None)),
+            Code::Unauthenticated,
+            "missing bearer token",
+        );
+        assert_status(
+            auth.authenticate(request(Some("Basic reader-token"))),
+            Code::Unauthenticated,
+            "missing bearer token",
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_token() {
+        let auth = auth();
+
+        assert_status(
+            authenticate(&auth, "reader-tokem"),
+            Code::Unauthenticated,
+            "invalid token",
+        );
+        assert_status(
+            authenticate(&auth, "reader-token-2"),
+            Code::Unauthenticated,
+            "invalid token",
+        );
+    }
+
+    #[test]
+    fn rejects_expired_or_wrong_issuer_jwt() {
+        let auth = auth();
+
+        let expired =
+            jwt(json!({ "iss": "bicycle", "exp": expires_in(-3600), "roles": ["reader"] }));
+        let wrong_issuer =
+            jwt(json!({ "iss": "other", "exp": expires_in(60), "roles": ["reader"] }));
+        let wrong_secret = jsonwebtoken::encode(
+            &Header::default(),
+            &json!({ "iss": "bicycle", "exp": expires_in(60), "roles": ["reader"] }),
+            &EncodingKey::from_secret(b"not the secret"),
+        )
+        .unwrap();
+
+        for token in [expired, wrong_issuer, wrong_secret] {
+            assert_status(
+                authenticate(&auth, &token),
+                Code::Unauthenticated,
+                "invalid token",
+            );
+        }
+    }
+
+    #[test]
+    fn reads_jwt_roles_from_array_or_string() {
+        let auth = auth();
+
+        let array =
+            jwt(json!({ "iss": "bicycle", "exp": expires_in(60), "roles": ["reader", "admin"] }));
+        let string =
+            jwt(json!({ "iss": "bicycle", "exp": expires_in(60), "roles": "reader admin" }));
+
+        for token in [array, string] {
+            let req = authenticate(&auth, &token).unwrap();
+
+            assert!(auth.authorize_model(&req, "Dog", ModelOp::Read).is_ok());
+            assert!(auth.authorize_admin(&req).is_ok());
+        }
+
+        let no_roles = jwt(json!({ "iss": "bicycle", "exp": expires_in(60) }));
+
+        assert_status(
+            authenticate(&auth, &no_roles),
+            Code::Unauthenticated,
+            "invalid token",
+        );
+    }
+
+    #[test]
+    fn unknown_roles() {
+        let config = CONFIG.replace(r#"roles = ["admin"]"#, r#"roles = ["ghost"]"#);
+        let err = Auth::new(Some(toml::from_str(&config).unwrap()))
+            .err()
+            .unwrap();
+
+        assert_eq!(
+            err.to_string(),
+            "an API token is granted unknown role 'ghost'"
+        );
+
+        // roles are only known when a JWT is verified, so unknown ones grant
+        // nothing
+        let auth = auth();
+        let ghost = jwt(json!({ "iss": "bicycle", "exp": expires_in(60), "roles": ["ghost"] }));
+        let req = authenticate(&auth, &ghost).unwrap();
+
+        assert!(auth.authorize_model(&req, "Dog", ModelOp::Read).is_err());
+        assert!(auth.authorize_admin(&req).is_err());
+    }
+
+    #[test]
+    fn compares_tokens() {
+        assert!(token_eq("reader-token", "reader-token"));
+        assert!(token_eq("", ""));
+        assert!(!token_eq("reader-token", "reader-tokem"));
+        assert!(!token_eq("reader-token", "reader-token-2"));
+        assert!(!token_eq("reader-token", ""));
+    }
+}
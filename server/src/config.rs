@@ -0,0 +1,233 @@
+/*
+BicycleDB is a protobuf-defined database management system.
+
+Copyright (C) 2024 Ordinary Labs
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::error::Error;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::Parser;
+use serde::Deserialize;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+
+use bicycle_core::{EngineTuning, Retention};
+
+use crate::auth::AuthConfig;
+
+/// read from the working directory when `--config` isn't passed.
+const DEFAULT_CONFIG: &str = "bicycle.toml";
+const DEFAULT_ADDR: &str = "[::0]:50051";
+const DEFAULT_SPROC_DIR: &str = "__bicycle.biplane__";
+
+/// BicycleDB server, each flag falls back to its environment variable and then
+/// to the config file.
+#[derive(Parser, Debug)]
+#[command(name = "bicycle_server", version)]
+struct Args {
+    /// path to a config file, defaults to ./bicycle.toml when it exists
+    #[arg(long, env = "BICYCLE_CONFIG")]
+    config: Option<PathBuf>,
+    /// address to listen at [default: [::0]:50051]
+    #[arg(long, env = "BICYCLE_ADDR")]
+    addr: Option<SocketAddr>,
+    /// directory the database is kept in [default: .]
+    #[arg(long, env = "BICYCLE_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+    /// directory deployed SPROCs are kept in [default: <DATA_DIR>/__bicycle.biplane__]
+    #[arg(long, env = "BICYCLE_SPROC_DIR")]
+    sproc_dir: Option<PathBuf>,
+    /// largest message in bytes the server accepts or sends [default: 4MiB in, unlimited out]
+    #[arg(long, env = "BICYCLE_MAX_MESSAGE_SIZE")]
+    max_message_size: Option<usize>,
+    /// requests handled at once per connection [default: unlimited]
+    #[arg(long, env = "BICYCLE_CONCURRENCY_LIMIT")]
+    concurrency_limit: Option<usize>,
+    /// HTTP/2 streams open at once per connection [default: unlimited]
+    #[arg(long, env = "BICYCLE_MAX_CONCURRENT_STREAMS")]
+    max_concurrent_streams: Option<u32>,
+    /// PEM certificate to serve TLS with, needs --tls-key
+    #[arg(long, env = "BICYCLE_TLS_CERT", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of --tls-cert
+    #[arg(long, env = "BICYCLE_TLS_KEY", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// PEM CA certificate that clients have to present a certificate signed by
+    #[arg(long, env = "BICYCLE_TLS_CLIENT_CA")]
+    tls_client_ca: Option<PathBuf>,
+    /// prints whether MODEL has records and exits, asked by `bicycle build`
+    #[arg(long, value_name = "MODEL", hide = true)]
+    has_records: Option<String>,
+    /// unpacks a backup into --data-dir and exits, asked by `bicycle restore`
+    #[arg(long, value_name = "ARCHIVE", hide = true)]
+    restore: Option<PathBuf>,
+}
+
+/// a `bicycle.toml`, whose paths are relative to the file.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    addr: Option<SocketAddr>,
+    data_dir: Option<PathBuf>,
+    sproc_dir: Option<PathBuf>,
+    max_message_size: Option<usize>,
+    concurrency_limit: Option<usize>,
+    max_concurrent_streams: Option<u32>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    tls_client_ca: Option<PathBuf>,
+    engine: EngineTuning,
+    changes: ChangeLog,
+    auth: Option<AuthConfig>,
+}
+
+/// the `[changes]` table, limits left out keep the engine's defaults.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ChangeLog {
+    max_changes: Option<u64>,
+    /// in seconds.
+    max_age: Option<u64>,
+}
+
+impl ChangeLog {
+    fn retention(&self) -> Retention {
+        let mut retention = Retention::default();
+
+        if let Some(max_changes) = self.max_changes {
+            retention.max_changes = Some(max_changes);
+        }
+
+        if let Some(max_age) = self.max_age {
+            retention.max_age = Some(Duration::from_secs(max_age));
+        }
+
+        retention
+    }
+}
+
+impl ConfigFile {
+    fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+
+        let mut file: ConfigFile = toml::from_str(&content)
+            .map_err(|err| format!("invalid config {}: {}", path.display(), err))?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        file.data_dir = file.data_dir.map(|data_dir| dir.join(data_dir));
+        file.sproc_dir = file.sproc_dir.map(|sproc_dir| dir.join(sproc_dir));
+        file.tls_cert = file.tls_cert.map(|path| dir.join(path));
+        file.tls_key = file.tls_key.map(|path| dir.join(path));
+        file.tls_client_ca = file.tls_client_ca.map(|path| dir.join(path));
+
+        if let Some(auth) = file.auth.as_mut() {
+            auth.resolve_paths(dir);
+        }
+
+        Ok(file)
+    }
+}
+
+/// how the server runs, see `Config::load`.
+#[derive(Debug)]
+pub(crate) struct Config {
+    pub(crate) addr: SocketAddr,
+    pub(crate) data_dir: PathBuf,
+    pub(crate) sproc_dir: PathBuf,
+    pub(crate) max_message_size: Option<usize>,
+    pub(crate) concurrency_limit: Option<usize>,
+    pub(crate) max_concurrent_streams: Option<u32>,
+    pub(crate) tls_cert: Option<PathBuf>,
+    pub(crate) tls_key: Option<PathBuf>,
+    pub(crate) tls_client_ca: Option<PathBuf>,
+    pub(crate) engine: EngineTuning,
+    pub(crate) retention: Retention,
+    /// only read from the config file, to keep secrets out of flags.
+    pub(crate) auth: Option<AuthConfig>,
+    pub(crate) has_records: Option<String>,
+    pub(crate) restore: Option<PathBuf>,
+}
+
+impl Config {
+    /// reads the settings from flags, then environment variables, then the
+    /// config file, then the defaults.
+    pub(crate) fn load() -> Result<Self, Box<dyn Error>> {
+        let args = Args::parse();
+
+        let file = match args.config {
+            Some(path) => ConfigFile::read(&path)?,
+            None if Path::new(DEFAULT_CONFIG).exists() => {
+                ConfigFile::read(Path::new(DEFAULT_CONFIG))?
+            }
+            None => ConfigFile::default(),
+        };
+
+        let data_dir = args.data_dir.or(file.data_dir).unwrap_or_default();
+
+        Ok(Self {
+            addr: match args.addr.or(file.addr) {
+                Some(addr) => addr,
+                None => DEFAULT_ADDR.parse()?,
+            },
+            sproc_dir: args
+                .sproc_dir
+                .or(file.sproc_dir)
+                .unwrap_or_else(|| data_dir.join(DEFAULT_SPROC_DIR)),
+            data_dir,
+            max_message_size: args.max_message_size.or(file.max_message_size),
+            concurrency_limit: args.concurrency_limit.or(file.concurrency_limit),
+            max_concurrent_streams: args.max_concurrent_streams.or(file.max_concurrent_streams),
+            tls_cert: args.tls_cert.or(file.tls_cert),
+            tls_key: args.tls_key.or(file.tls_key),
+            tls_client_ca: args.tls_client_ca.or(file.tls_client_ca),
+            engine: file.engine,
+            retention: file.changes.retention(),
+            auth: file.auth,
+            has_records: args.has_records,
+            restore: args.restore,
+        })
+    }
+
+    /// TLS config when a certificate is set, verifying clients when a client CA
+    /// is set too.
+    pub(crate) fn tls(&self) -> Result<Option<ServerTlsConfig>, Box<dyn Error>> {
+        let (cert, key) = match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => (read_pem(cert)?, read_pem(key)?),
+            (None, None) if self.tls_client_ca.is_some() => {
+                return Err("tls_client_ca needs tls_cert and tls_key".into())
+            }
+            (None, None) => return Ok(None),
+            _ => return Err("tls_cert and tls_key have to be set together".into()),
+        };
+
+        let mut tls = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+
+        if let Some(client_ca) = &self.tls_client_ca {
+            tls = tls.client_ca_root(Certificate::from_pem(read_pem(client_ca)?));
+        }
+
+        Ok(Some(tls))
+    }
+}
+
+fn read_pem(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    fs::read(path).map_err(|err| format!("failed to read {}: {}", path.display(), err).into())
+}
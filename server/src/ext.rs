@@ -0,0 +1,29 @@
+/*
+Bicycle is a framework for managing data.
+
+Copyright (C) 2024 Ordinary Labs
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as
+published by the Free Software Foundation, either version 3 of the
+License, or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+//! custom code for the server. `bicycle build` generates this file once and
+//! keeps it as is when regenerating, unlike the rest of the crate.
+
+use tonic::transport::server::Router;
+
+/// adds services of your own to the ones the server generates, i.e.
+/// `router.add_service(MyServer::new(MyService {}))`.
+pub(crate) fn routes(router: Router) -> Router {
+    router
+}
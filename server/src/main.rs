@@ -19,29 +19,123 @@ along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::error::Error;
 
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use tonic::service::interceptor::InterceptedService;
 use tonic::transport::Server;
-use tonic::{Request, Response, Status};
+use tonic::{Request, Response, Status, Streaming};
 
 use bicycle_core;
 use bicycle_proto as proto;
 
+mod auth;
+mod config;
+mod ext;
+
+use auth::{BiplaneOp, ModelOp};
+
 use proto::bicycle_server::{Bicycle, BicycleServer};
-use proto::IndexQuery;
 use proto::FILE_DESCRIPTOR_SET;
+use proto::{ChangesQuery, IndexQuery, SnapshotLease, WatchQuery};
 
 #[global_allocator]
 static GLOBAL: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
+const STREAM_BUFFER_SIZE: usize = 128;
+
+const DEFAULT_INGEST_BATCH_SIZE: usize = 1000;
+/// largest batch a client can ask for, which bounds the records an ingest
+/// holds in memory.
+const MAX_INGEST_BATCH_SIZE: usize = 100_000;
+const INGEST_BATCH_SIZE_HEADER: &str = "x-bicycle-batch-size";
+
+/// number of records committed per batch by `Ingest*` RPCs, clients can
+/// override the default with the `x-bicycle-batch-size` header, up to
+/// `MAX_INGEST_BATCH_SIZE`.
+fn ingest_batch_size<T>(req: &Request<T>) -> usize {
+    req.metadata()
+        .get(INGEST_BATCH_SIZE_HEADER)
+        .and_then(|size| size.to_str().ok())
+        .and_then(|size| size.parse().ok())
+        .filter(|size| *size > 0)
+        .map(|size: usize| size.min(MAX_INGEST_BATCH_SIZE))
+        .unwrap_or(DEFAULT_INGEST_BATCH_SIZE)
+}
+
+const SNAPSHOT_HEADER: &str = "x-bicycle-snapshot";
+
+/// id of the snapshot a read RPC should read from, if it was sent with one.
+fn snapshot_id<T>(req: &Request<T>) -> Option<String> {
+    req.metadata()
+        .get(SNAPSHOT_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .map(|id| id.to_string())
+}
+
+/// runs `read` against `snapshot` when there is one.
+fn read_snapshot<F, R>(snapshot: Option<String>, read: F) -> Result<R, Box<dyn Error>>
+where
+    F: FnOnce() -> Result<R, Box<dyn Error>>,
+{
+    match snapshot {
+        Some(id) => bicycle_core::with_snapshot(&id, read),
+        None => read(),
+    }
+}
+
 pub struct BicycleService {}
 
 #[tonic::async_trait]
 impl Bicycle for BicycleService {
+    async fn get_changes(
+        &self,
+        req: Request<ChangesQuery>,
+    ) -> Result<Response<proto::Changes>, Status> {
+        auth::authorize_model(&req, "*", ModelOp::Read)?;
+
+        match bicycle_core::get_changes(req.into_inner()) {
+            Ok(changes) => Ok(Response::new(changes)),
+            Err(err) => {
+                let msg = format!("failed to GET changes: {}", err);
+                Err(Status::internal(msg))
+            }
+        }
+    }
+
+    async fn begin_snapshot(
+        &self,
+        req: Request<SnapshotLease>,
+    ) -> Result<Response<proto::Snapshot>, Status> {
+        auth::authorize_model(&req, "*", ModelOp::Read)?;
+
+        match bicycle_core::begin_snapshot(req.into_inner()) {
+            Ok(snapshot) => Ok(Response::new(snapshot)),
+            Err(err) => {
+                let msg = format!("failed to BEGIN snapshot: {}", err);
+                Err(Status::resource_exhausted(msg))
+            }
+        }
+    }
+
+    async fn end_snapshot(&self, req: Request<proto::Snapshot>) -> Result<Response<()>, Status> {
+        auth::authorize_model(&req, "*", ModelOp::Read)?;
+
+        bicycle_core::end_snapshot(req.into_inner());
+        Ok(Response::new(()))
+    }
+
     // ##START_HANDLERS##
     async fn get_examples_by_pk(
         &self,
         req: Request<IndexQuery>,
     ) -> Result<Response<proto::Examples>, Status> {
-        match bicycle_core::get_examples_by_pk(req.into_inner()) {
+        auth::authorize_model(&req, "Example", ModelOp::Read)?;
+
+        let snapshot = snapshot_id(&req);
+        let query = req.into_inner();
+
+        match read_snapshot(snapshot, || bicycle_core::get_examples_by_pk(query)) {
             Ok(items) => Ok(Response::new(proto::Examples { examples: items })),
             Err(err) => {
                 let msg = format!("failed to GET 'Examples': {}", err.to_string());
@@ -50,10 +144,51 @@ impl Bicycle for BicycleService {
         }
     }
 
+    type StreamExamplesByPkStream = ReceiverStream<Result<proto::Example, Status>>;
+
+    async fn stream_examples_by_pk(
+        &self,
+        req: Request<IndexQuery>,
+    ) -> Result<Response<Self::StreamExamplesByPkStream>, Status> {
+        auth::authorize_model(&req, "Example", ModelOp::Read)?;
+
+        let snapshot = snapshot_id(&req);
+        let query = req.into_inner();
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER_SIZE);
+
+        tokio::task::spawn_blocking(move || {
+            let items = match read_snapshot(snapshot, || bicycle_core::stream_examples_by_pk(query))
+            {
+                Ok(items) => items,
+                Err(err) => {
+                    let msg = format!("failed to STREAM 'Examples': {}", err);
+                    let _ = tx.blocking_send(Err(Status::internal(msg)));
+                    return;
+                }
+            };
+
+            for item in items {
+                let item = item.map_err(|err| {
+                    let msg = format!("failed to STREAM 'Examples': {}", err);
+                    Status::internal(msg)
+                });
+
+                // receiver is dropped when the client goes away
+                if tx.blocking_send(item).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
     async fn delete_examples_by_pk(
         &self,
         req: Request<IndexQuery>,
     ) -> Result<Response<()>, Status> {
+        auth::authorize_model(&req, "Example", ModelOp::Delete)?;
+
         match bicycle_core::delete_examples_by_pk(req.into_inner()) {
             Ok(_) => Ok(Response::new(())),
             Err(err) => {
@@ -64,6 +199,8 @@ impl Bicycle for BicycleService {
     }
 
     async fn put_example(&self, req: Request<proto::Example>) -> Result<Response<()>, Status> {
+        auth::authorize_model(&req, "Example", ModelOp::Write)?;
+
         if let Err(err) = bicycle_core::put_example(req.into_inner()) {
             let msg = format!("failed to PUT 'Example': {}", err.to_string());
 
@@ -77,6 +214,8 @@ impl Bicycle for BicycleService {
         &self,
         req: Request<proto::Examples>,
     ) -> Result<Response<()>, Status> {
+        auth::authorize_model(&req, "Example", ModelOp::Write)?;
+
         if let Err(err) = bicycle_core::batch_put_examples(req.into_inner()) {
             let msg = format!("failed to BATCH PUT 'Examples': {}", err.to_string());
 
@@ -85,14 +224,262 @@ impl Bicycle for BicycleService {
 
         Ok(Response::new(()))
     }
+
+    type IngestExamplesStream = ReceiverStream<Result<proto::IngestSummary, Status>>;
+
+    async fn ingest_examples(
+        &self,
+        req: Request<Streaming<proto::Example>>,
+    ) -> Result<Response<Self::IngestExamplesStream>, Status> {
+        auth::authorize_model(&req, "Example", ModelOp::Write)?;
+
+        let batch_size = ingest_batch_size(&req);
+        let mut stream = req.into_inner();
+
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER_SIZE);
+
+        tokio::spawn(async move {
+            let mut summary = proto::IngestSummary::default();
+            let mut batch = vec![];
+
+            loop {
+                let item = match stream.message().await {
+                    Ok(item) => item,
+                    Err(status) => {
+                        let _ = tx.send(Err(status)).await;
+                        break;
+                    }
+                };
+                let done = item.is_none();
+
+                if let Some(item) = item {
+                    batch.push(item);
+                }
+
+                let commit = batch.len() >= batch_size || (done && !batch.is_empty());
+
+                if commit {
+                    let len = batch.len() as u64;
+
+                    match bicycle_core::ingest_examples(std::mem::take(&mut batch)) {
+                        Ok(_) => summary.written += len,
+                        Err(err) => {
+                            summary.failed += len;
+                            log::error!("failed to INGEST 'Examples': {}", err);
+                        }
+                    }
+
+                    log::info!(
+                        "ingest 'Examples': {} written, {} failed",
+                        summary.written,
+                        summary.failed
+                    );
+                }
+
+                // the last summary sent is the totals, even for an empty ingest
+                if (commit || done) && tx.send(Ok(summary.clone())).await.is_err() {
+                    break;
+                }
+
+                if done {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type WatchExamplesStream = ReceiverStream<Result<proto::ExampleChange, Status>>;
+
+    async fn watch_examples(
+        &self,
+        req: Request<WatchQuery>,
+    ) -> Result<Response<Self::WatchExamplesStream>, Status> {
+        auth::authorize_model(&req, "Example", ModelOp::Read)?;
+
+        let mut watcher = match bicycle_core::watch_examples(req.into_inner()) {
+            Ok(watcher) => watcher,
+            Err(err) => {
+                let msg = format!("failed to WATCH 'Examples': {}", err);
+                return Err(Status::failed_precondition(msg));
+            }
+        };
+
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER_SIZE);
+
+        tokio::spawn(async move {
+            loop {
+                // stops waiting as soon as the client goes away
+                let change = tokio::select! {
+                    change = watcher.next() => change.map_err(|err| {
+                        let msg = format!("failed to WATCH 'Examples': {}", err);
+                        Status::aborted(msg)
+                    }),
+                    _ = tx.closed() => break,
+                };
+
+                let failed = change.is_err();
+
+                if tx.send(change).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
     // ##END_HANDLERS##
 }
 
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use proto::admin_server::{Admin, AdminServer};
+use proto::{BackupChunk, ExportQuery, JsonLine};
+
+const BACKUP_CHUNK_SIZE: usize = 64 * 1024;
+
+static BACKUP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+pub struct AdminService {}
+
+#[tonic::async_trait]
+impl Admin for AdminService {
+    type BackupStream = ReceiverStream<Result<BackupChunk, Status>>;
+
+    async fn backup(&self, req: Request<()>) -> Result<Response<Self::BackupStream>, Status> {
+        auth::authorize_admin(&req)?;
+
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER_SIZE);
+
+        tokio::task::spawn_blocking(move || {
+            let path = std::env::temp_dir().join(format!(
+                "bicycle-backup-{}-{}.tar",
+                std::process::id(),
+                BACKUP_COUNT.fetch_add(1, Ordering::Relaxed)
+            ));
+
+            let res = bicycle_core::backup(&path).and_then(|manifest| {
+                log::info!("backup at seq {}", manifest.seq);
+
+                let mut file = File::open(&path)?;
+                let mut buf = vec![0; BACKUP_CHUNK_SIZE];
+
+                loop {
+                    let n = file.read(&mut buf)?;
+
+                    if n == 0 {
+                        break;
+                    }
+
+                    let chunk = BackupChunk {
+                        data: buf[..n].to_vec(),
+                    };
+
+                    // receiver is dropped when the client goes away
+                    if tx.blocking_send(Ok(chunk)).is_err() {
+                        break;
+                    }
+                }
+
+                Ok(())
+            });
+
+            if let Err(err) = res {
+                let msg = format!("failed to BACKUP: {}", err);
+                let _ = tx.blocking_send(Err(Status::internal(msg)));
+            }
+
+            let _ = remove_file(&path);
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type ExportStream = ReceiverStream<Result<JsonLine, Status>>;
+
+    async fn export(
+        &self,
+        req: Request<ExportQuery>,
+    ) -> Result<Response<Self::ExportStream>, Status> {
+        auth::authorize_admin(&req)?;
+
+        let query = req.into_inner();
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER_SIZE);
+
+        tokio::task::spawn_blocking(move || {
+            let lines = match bicycle_core::export(&query.models) {
+                Ok(lines) => lines,
+                Err(err) => {
+                    let msg = format!("failed to EXPORT: {}", err);
+                    let _ = tx.blocking_send(Err(Status::invalid_argument(msg)));
+                    return;
+                }
+            };
+
+            for line in lines {
+                let line = line.map(|line| JsonLine { line }).map_err(|err| {
+                    let msg = format!("failed to EXPORT: {}", err);
+                    Status::internal(msg)
+                });
+
+                // receiver is dropped when the client goes away
+                if tx.blocking_send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn import(
+        &self,
+        req: Request<Streaming<JsonLine>>,
+    ) -> Result<Response<proto::IngestSummary>, Status> {
+        auth::authorize_admin(&req)?;
+
+        let batch_size = ingest_batch_size(&req);
+        let mut stream = req.into_inner();
+
+        let mut summary = proto::IngestSummary::default();
+        let mut batch = vec![];
+
+        loop {
+            let item = stream.message().await?;
+            let done = item.is_none();
+
+            if let Some(item) = item {
+                batch.push(item.line);
+            }
+
+            if batch.len() >= batch_size || (done && !batch.is_empty()) {
+                let imported = bicycle_core::import(std::mem::take(&mut batch));
+
+                summary.written += imported.written;
+                summary.failed += imported.failed;
+
+                log::info!(
+                    "import: {} written, {} failed",
+                    summary.written,
+                    summary.failed
+                );
+            }
+
+            if done {
+                break;
+            }
+        }
+
+        Ok(Response::new(summary))
+    }
+}
+
 use parking_lot::RwLock;
 use std::collections::BTreeMap;
-use std::fs::{create_dir, read_dir, remove_file, File};
+use std::fs::{create_dir_all, read_dir, remove_file, File};
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::PathBuf;
 
 use bicycle_core::biplane::wasmtime::{Engine, Module};
 use bicycle_core::biplane::{compile_module, invoke_module};
@@ -100,24 +487,21 @@ use bicycle_core::biplane::{compile_module, invoke_module};
 use proto::biplane_server::{Biplane, BiplaneServer};
 use proto::{Fn, FnName, Fns, OneOff, Stored};
 
-const SCRIPT_DIR: &'static str = "__bicycle.biplane__";
-
 pub struct BiplaneService {
     engine: Engine,
     modules: RwLock<BTreeMap<String, Module>>,
+    function_dir: PathBuf,
 }
 
 impl BiplaneService {
-    pub fn new() -> Result<Self, Box<dyn Error>> {
-        let function_dir = Path::new(SCRIPT_DIR);
-
+    pub fn new(function_dir: PathBuf) -> Result<Self, Box<dyn Error>> {
         let engine = Engine::default();
         let mut modules = BTreeMap::new();
 
         if !function_dir.exists() {
-            create_dir(function_dir)?;
+            create_dir_all(&function_dir)?;
         } else {
-            let paths = read_dir(function_dir)?;
+            let paths = read_dir(&function_dir)?;
 
             for path in paths {
                 let path = path.unwrap().path();
@@ -134,6 +518,7 @@ impl BiplaneService {
         Ok(Self {
             engine,
             modules: RwLock::new(modules),
+            function_dir,
         })
     }
 }
@@ -141,10 +526,11 @@ impl BiplaneService {
 #[tonic::async_trait]
 impl Biplane for BiplaneService {
     async fn remove(&self, req: Request<FnName>) -> Result<Response<()>, Status> {
+        auth::authorize_biplane(&req, BiplaneOp::Remove)?;
+
         let name = req.into_inner().name;
-        let function_dir = Path::new(SCRIPT_DIR);
 
-        remove_file(function_dir.join(&name))?;
+        remove_file(self.function_dir.join(&name))?;
 
         self.modules.write().remove(&name);
 
@@ -152,10 +538,11 @@ impl Biplane for BiplaneService {
     }
 
     async fn deploy(&self, req: Request<Fn>) -> Result<Response<()>, Status> {
+        auth::authorize_biplane(&req, BiplaneOp::Deploy)?;
+
         let Fn { name, function } = req.into_inner();
-        let function_dir = Path::new(SCRIPT_DIR);
 
-        let mut file = File::create(function_dir.join(&name))?;
+        let mut file = File::create(self.function_dir.join(&name))?;
         file.write_all(&function)?;
 
         if let Ok(module) = compile_module(&function, &self.engine) {
@@ -166,7 +553,9 @@ impl Biplane for BiplaneService {
         }
     }
 
-    async fn list(&self, _req: Request<()>) -> Result<Response<Fns>, Status> {
+    async fn list(&self, req: Request<()>) -> Result<Response<Fns>, Status> {
+        auth::authorize_biplane(&req, BiplaneOp::Invoke)?;
+
         let mut functions = vec![];
 
         for (name, _) in &*self.modules.read() {
@@ -184,6 +573,8 @@ impl Biplane for BiplaneService {
         &self,
         req: Request<OneOff>,
     ) -> Result<Response<prost_types::Value>, Status> {
+        auth::authorize_biplane(&req, BiplaneOp::Deploy)?;
+
         let OneOff { function, args } = req.into_inner();
 
         if let Ok(module) = compile_module(&function, &self.engine) {
@@ -200,6 +591,8 @@ impl Biplane for BiplaneService {
         &self,
         req: Request<Stored>,
     ) -> Result<Response<prost_types::Value>, Status> {
+        auth::authorize_biplane(&req, BiplaneOp::Invoke)?;
+
         let Stored { name, args } = req.into_inner();
 
         if let Some(function) = self.modules.read().get(&name) {
@@ -220,21 +613,98 @@ impl Biplane for BiplaneService {
 async fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init();
 
-    let addr = "[::0]:50051".parse()?;
+    let mut config = config::Config::load()?;
+
+    // `bicycle restore` unpacks backups here, so they're checked against the
+    // engine this server was built with. the manifest is written to stdout
+    if let Some(archive) = config.restore.as_deref() {
+        match bicycle_core::restore(archive, &config.data_dir) {
+            Ok(manifest) => {
+                std::io::stdout().write_all(&prost::Message::encode_to_vec(&manifest))?;
+                return Ok(());
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    bicycle_core::configure(bicycle_core::EngineConfig {
+        data_dir: config.data_dir.clone(),
+        tuning: config.engine.clone(),
+        // `bicycle dev` asks while its running server has the store open
+        read_only: config.has_records.is_some(),
+    })?;
+
+    // `bicycle build` asks the previous build whether models it's about to
+    // drop still have records
+    if let Some(model) = config.has_records.as_deref() {
+        println!("{}", bicycle_core::has_records(model)?);
+        return Ok(());
+    }
+
+    // brings stored records up to this build's schema version before serving
+    bicycle_core::migrate()?;
+
+    bicycle_core::set_change_retention(config.retention.clone());
+
+    // tonic's own defaults when no limit is set
+    let max_decoding_message_size = config.max_message_size.unwrap_or(4 * 1024 * 1024);
+    let max_encoding_message_size = config.max_message_size.unwrap_or(usize::MAX);
 
     let reflection_service = tonic_reflection::server::Builder::configure()
         .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
         .build()
         .unwrap();
 
-    log::info!("Bicycle Server 🚲 listening at: {}", addr);
+    let tls = config.tls()?;
+
+    if !auth::init(config.auth.take())? && !config.addr.ip().is_loopback() {
+        log::warn!("auth is disabled, anyone who can reach the server has full access");
+    }
+
+    log::info!(
+        "Bicycle Server 🚲 listening at: {}{}",
+        config.addr,
+        if tls.is_some() { " (TLS)" } else { "" }
+    );
+
+    let mut builder = Server::builder().max_concurrent_streams(config.max_concurrent_streams);
+
+    if let Some(tls) = tls {
+        builder = builder.tls_config(tls)?;
+    }
+
+    if let Some(limit) = config.concurrency_limit {
+        builder = builder.concurrency_limit_per_connection(limit);
+    }
 
-    Server::builder()
-        .add_service(BicycleServer::new(BicycleService {}))
-        .add_service(BiplaneServer::new(BiplaneService::new()?))
-        .add_service(reflection_service)
-        .serve(addr)
-        .await?;
+    let router = builder
+        .add_service(InterceptedService::new(
+            BicycleServer::new(BicycleService {})
+                .max_decoding_message_size(max_decoding_message_size)
+                .max_encoding_message_size(max_encoding_message_size),
+            auth::authenticate,
+        ))
+        .add_service(InterceptedService::new(
+            AdminServer::new(AdminService {})
+                .max_decoding_message_size(max_decoding_message_size)
+                .max_encoding_message_size(max_encoding_message_size),
+            auth::authenticate,
+        ))
+        .add_service(InterceptedService::new(
+            BiplaneServer::new(BiplaneService::new(config.sproc_dir)?)
+                .max_decoding_message_size(max_decoding_message_size)
+                .max_encoding_message_size(max_encoding_message_size),
+            auth::authenticate,
+        ))
+        .add_service(InterceptedService::new(
+            reflection_service,
+            auth::authenticate,
+        ));
+
+    ext::routes(router).serve(config.addr).await?;
 
     Ok(())
 }
@@ -23,8 +23,12 @@ pub use prost_types;
 mod models;
 pub use models::*;
 
+pub mod ext;
+
 pub mod proto {
+    // ##START_INCLUDE_PROTO##
     tonic::include_proto!("bicycle");
+    // ##END_INCLUDE_PROTO##
 }
 
 use std::error::Error;